@@ -0,0 +1,314 @@
+use std::cmp::Reverse;
+
+use crate::notes::NoteInfo;
+use crate::songbuilder::SongBuilder;
+
+// The arranger turns polyphonic music, where any number of notes can be playing
+// at once, into one voice per motor, each playing one note at a time.
+//
+// It works through the notes in order of their start times. Whenever some
+// notes start, they compete for the motors with the notes that are already
+// playing, and the `Priority` decides which of them get to play: a note that's
+// already playing and loses is cut short, and a new note that loses is dropped.
+// Then the new notes that won are given free motors, as the `VoiceLeading`
+// says.
+//
+// Each motor's voice has rests in the gaps between its notes, and all of the
+// voices are padded to end at the same time, with an exit note.
+
+/// A note in a piece of polyphonic music.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PolyNote {
+    pub start_mcs: u64,
+    pub length_mcs: u64,
+    pub frequency_mchz: u64,
+}
+
+impl PolyNote {
+    fn end_mcs(&self) -> u64 {
+        self.start_mcs + self.length_mcs
+    }
+}
+
+/// Which notes get to play when there are more notes than motors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Priority {
+    /// The highest notes win, which keeps the melody.
+    Skyline,
+    /// The lowest note wins, and then the highest notes, which keeps the bass
+    /// line as well as the melody.
+    BassAndSkyline,
+    /// Notes that are already playing win, and then the highest new notes, so
+    /// that notes are never cut short.
+    FirstCome,
+}
+
+/// How new notes are given to free motors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VoiceLeading {
+    /// Give each note to the free motor whose last note was closest to it in
+    /// pitch, so that each motor plays as smooth a line as it can.
+    Closest,
+    /// Give the highest note to the lowest-numbered free motor, and so on.
+    HighestFirst,
+}
+
+#[derive(Clone, Debug)]
+pub struct Arranger {
+    pub motors: usize,
+    pub priority: Priority,
+    pub voice_leading: VoiceLeading,
+}
+
+/// A note that the arranger couldn't play all of.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DroppedNote {
+    pub note: PolyNote,
+    /// How much of the note was played before it was cut short. This is 0 for
+    /// a note that wasn't played at all.
+    pub played_mcs: u64,
+}
+
+#[derive(Debug)]
+pub struct Arrangement {
+    pub song: SongBuilder,
+    pub dropped: Vec<DroppedNote>,
+}
+
+/// A note that's competing for a motor.
+struct Candidate {
+    note: PolyNote,
+    /// The motor that the note is already playing on, if it's playing.
+    motor: Option<usize>,
+}
+
+fn note_info(frequency_mchz: u64, length_mcs: u64) -> NoteInfo {
+    NoteInfo {
+        next_note_index: 0,
+        motor_id: 0,
+        exit: false,
+        stop: false,
+        frequency_mchz,
+        length_mcs,
+        rearticulate: true,
+        repeat: None,
+    }
+}
+
+/// How far apart two frequencies are, in octaves.
+fn distance(a_mchz: u64, b_mchz: u64) -> f64 {
+    (a_mchz as f64 / b_mchz as f64).log2().abs()
+}
+
+impl Arranger {
+    /// An arranger for the given number of motors (at most 256), which keeps
+    /// the bass line and the melody and makes each motor's line smooth.
+    pub fn new(motors: usize) -> Self {
+        Arranger { motors, priority: Priority::BassAndSkyline, voice_leading: VoiceLeading::Closest }
+    }
+
+    /// Put candidates in order of priority, from the ones that should play to
+    /// the ones that should be dropped first.
+    fn rank(&self, candidates: &mut [Candidate]) {
+        // On a tie, notes that are already playing win.
+        match self.priority {
+            Priority::Skyline =>
+                candidates.sort_by_key(|c| (Reverse(c.note.frequency_mchz), c.motor.is_none())),
+            Priority::BassAndSkyline => {
+                candidates.sort_by_key(|c| (Reverse(c.note.frequency_mchz), c.motor.is_none()));
+
+                if let Some(lowest) = (0..candidates.len()).min_by_key(|&i|
+                    (candidates[i].note.frequency_mchz, candidates[i].motor.is_none()))
+                {
+                    candidates[..=lowest].rotate_right(1);
+                }
+            }
+            Priority::FirstCome =>
+                candidates.sort_by_key(|c| (c.motor.is_none(), Reverse(c.note.frequency_mchz))),
+        }
+    }
+
+    /// Choose a free motor for a note, given the frequency of each motor's last
+    /// note.
+    fn choose_motor(&self, note: &PolyNote, free: &[usize], last_frequencies: &[Option<u64>]) -> usize {
+        match self.voice_leading {
+            VoiceLeading::Closest => *free.iter()
+                .min_by(|&&a, &&b| {
+                    let distance_to = |motor: usize| last_frequencies[motor]
+                        .map_or(f64::INFINITY, |last| distance(last, note.frequency_mchz));
+                    distance_to(a).partial_cmp(&distance_to(b)).unwrap().then(a.cmp(&b))
+                })
+                .unwrap(),
+            VoiceLeading::HighestFirst => free[0],
+        }
+    }
+
+    /// Arrange some notes onto the motors. Rests (notes with a frequency of 0)
+    /// and notes with no length are left out.
+    pub fn arrange(&self, notes: &[PolyNote]) -> Arrangement {
+        assert!(self.motors <= u8::MAX as usize + 1, "too many motors");
+
+        let mut notes: Vec<PolyNote> = notes.iter()
+            .filter(|n| n.frequency_mchz > 0 && n.length_mcs > 0)
+            .copied()
+            .collect();
+        notes.sort_by_key(|n| (n.start_mcs, Reverse(n.frequency_mchz)));
+
+        // The notes each motor plays, and the one that it's playing now.
+        let mut motor_notes: Vec<Vec<PolyNote>> = vec![vec![]; self.motors];
+        let mut playing: Vec<Option<PolyNote>> = vec![None; self.motors];
+        let mut dropped: Vec<DroppedNote> = vec![];
+
+        let mut first: usize = 0;
+
+        while first < notes.len() {
+            let now_mcs: u64 = notes[first].start_mcs;
+            let count: usize = notes[first..].iter().take_while(|n| n.start_mcs == now_mcs).count();
+
+            for motor_playing in &mut playing {
+                if motor_playing.is_some_and(|n| n.end_mcs() <= now_mcs) {
+                    *motor_playing = None;
+                }
+            }
+
+            let mut candidates: Vec<Candidate> = playing.iter()
+                .enumerate()
+                .filter_map(|(motor, n)| n.map(|note| Candidate { note, motor: Some(motor) }))
+                .chain(notes[first..first + count].iter().map(|&note| Candidate { note, motor: None }))
+                .collect();
+
+            self.rank(&mut candidates);
+
+            for loser in candidates.drain(self.motors.min(candidates.len())..) {
+                let played_mcs: u64 = match loser.motor {
+                    Some(motor) => {
+                        playing[motor] = None;
+                        let cut_note: &mut PolyNote = motor_notes[motor].last_mut().unwrap();
+                        cut_note.length_mcs = now_mcs - cut_note.start_mcs;
+                        cut_note.length_mcs
+                    }
+                    None => 0,
+                };
+
+                dropped.push(DroppedNote { note: loser.note, played_mcs });
+            }
+
+            let mut winners: Vec<PolyNote> = candidates.iter()
+                .filter(|c| c.motor.is_none())
+                .map(|c| c.note)
+                .collect();
+
+            if self.voice_leading == VoiceLeading::HighestFirst {
+                winners.sort_by_key(|n| Reverse(n.frequency_mchz));
+            }
+
+            for note in winners {
+                let free: Vec<usize> = (0..self.motors).filter(|&m| playing[m].is_none()).collect();
+                let last_frequencies: Vec<Option<u64>> = motor_notes.iter()
+                    .map(|n| n.last().map(|last| last.frequency_mchz))
+                    .collect();
+
+                let motor: usize = self.choose_motor(&note, &free, &last_frequencies);
+                motor_notes[motor].push(note);
+                playing[motor] = Some(note);
+            }
+
+            first += count;
+        }
+
+        let song_end_mcs: u64 = motor_notes.iter().flatten().map(|n| n.end_mcs()).max().unwrap_or(0);
+        let mut song: SongBuilder = SongBuilder::new();
+
+        for (motor, notes) in motor_notes.iter().enumerate() {
+            let mut current_mcs: u64 = 0;
+
+            for note in notes {
+                if note.start_mcs > current_mcs {
+                    song.add(motor as u8, note_info(0, note.start_mcs - current_mcs));
+                }
+
+                song.add(motor as u8, note_info(note.frequency_mchz, note.length_mcs));
+                current_mcs = note.end_mcs();
+            }
+
+            if song_end_mcs > current_mcs {
+                song.add(motor as u8, note_info(0, song_end_mcs - current_mcs));
+            }
+
+            song.add(motor as u8, note_info(0, 0).exit());
+        }
+
+        Arrangement { song, dropped }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::arranger::*;
+
+    fn note(start_mcs: u64, length_mcs: u64, frequency_hz: u64) -> PolyNote {
+        PolyNote { start_mcs, length_mcs, frequency_mchz: frequency_hz * 1_000_000 }
+    }
+
+    /// Each motor's notes, as (frequency in hertz, length) pairs.
+    fn lines(song: &SongBuilder) -> Vec<Vec<(u64, u64)>> {
+        song.voices.iter().map(|voice| {
+            let mut line: Vec<(u64, u64)> = vec![];
+            let mut note: NoteInfo = song.notes[voice.first_note_index as usize];
+
+            while !note.exit {
+                line.push((note.frequency_mchz / 1_000_000, note.length_mcs));
+                note = song.notes[note.next_note_index as usize];
+            }
+
+            line
+        }).collect()
+    }
+
+    #[test]
+    fn keeps_bass_and_melody() {
+        let notes: Vec<PolyNote> = vec![note(0, 100, 220), note(0, 100, 330), note(0, 100, 440)];
+        let arrangement: Arrangement = Arranger::new(2).arrange(&notes);
+
+        assert_eq!(lines(&arrangement.song), vec![vec![(220, 100)], vec![(440, 100)]]);
+        assert_eq!(arrangement.dropped, vec![DroppedNote { note: notes[1], played_mcs: 0 }]);
+    }
+
+    #[test]
+    fn cuts_notes_short() {
+        let notes: Vec<PolyNote> = vec![note(0, 300, 220), note(100, 100, 440), note(150, 100, 110)];
+
+        let mut arranger: Arranger = Arranger { priority: Priority::Skyline, ..Arranger::new(1) };
+        let arrangement: Arrangement = arranger.arrange(&notes);
+
+        // The high note cuts off the low one, and the lowest one is dropped.
+        assert_eq!(lines(&arrangement.song), vec![vec![(220, 100), (440, 100)]]);
+        assert_eq!(arrangement.dropped, vec![
+            DroppedNote { note: notes[0], played_mcs: 100 },
+            DroppedNote { note: notes[2], played_mcs: 0 },
+        ]);
+
+        arranger.priority = Priority::FirstCome;
+        assert_eq!(lines(&arranger.arrange(&notes).song), vec![vec![(220, 300)]]);
+    }
+
+    #[test]
+    fn leads_voices() {
+        let notes: Vec<PolyNote> = vec![
+            note(0, 200, 220), note(100, 100, 440),
+            note(200, 100, 247), note(200, 100, 523),
+        ];
+
+        let mut arranger: Arranger = Arranger::new(2);
+        assert_eq!(lines(&arranger.arrange(&notes).song), vec![
+            vec![(220, 200), (247, 100)],
+            vec![(0, 100), (440, 100), (523, 100)],
+        ]);
+
+        arranger.voice_leading = VoiceLeading::HighestFirst;
+        assert_eq!(lines(&arranger.arrange(&notes).song), vec![
+            vec![(220, 200), (523, 100)],
+            vec![(0, 100), (440, 100), (247, 100)],
+        ]);
+    }
+}
@@ -0,0 +1,204 @@
+use std::error::Error;
+
+#[cfg(feature = "rodio")]
+use rodio::{
+    OutputStream,
+    Sink,
+};
+
+use crate::control::PlaybackControl;
+
+use crate::machine::Machine;
+
+#[cfg(feature = "raspi")]
+use crate::motor::Motor;
+use crate::motor::Pulse;
+use crate::motor::SimpleAudioMotor;
+
+use crate::notes::NoteInfo;
+use crate::notes::play_note_info_array;
+#[cfg(any(feature = "raspi", feature = "rodio"))]
+use crate::notes::play_note_info_array_with_control;
+use crate::notes::Voice;
+
+#[cfg(feature = "raspi")]
+use crate::shutdown;
+
+#[cfg(feature = "rodio")]
+use crate::stream::sample_stream;
+
+#[cfg(feature = "raspi")]
+use crate::timer::NixTimer;
+use crate::timer::SimpleAudioTimer;
+
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// A way of playing songs. Every backend that this build of ambrose includes is
+/// listed by `backends()`, so the backend can be chosen at runtime.
+pub trait Backend {
+    /// The name used to select this backend on the command line.
+    fn name(&self) -> &'static str;
+
+    /// A short description of this backend.
+    fn description(&self) -> &'static str;
+
+    /// Whether `control` has any effect while this backend is playing.
+    fn supports_control(&self) -> bool { false }
+
+    /// Play a song on the given machine. Backends that don't use real motors
+    /// may ignore the machine.
+    fn play(
+        &self,
+        machine: &Machine,
+        notes: Vec<NoteInfo>,
+        voices: Vec<Voice>,
+        control: &PlaybackControl,
+    ) -> Result<(), Box<dyn Error>>;
+}
+
+/// All of the backends included in this build, with the default one first.
+pub fn backends() -> Vec<Box<dyn Backend>> {
+    vec![
+        #[cfg(feature = "raspi")]
+        Box::new(GpioBackend { }),
+        Box::new(AudioBackend { }),
+    ]
+}
+
+pub fn find_backend(name: &str) -> Result<Box<dyn Backend>, String> {
+    backends().into_iter().find(|b| b.name() == name).ok_or_else(|| {
+        let names: Vec<&str> = backends().iter().map(|b| b.name()).collect();
+        format!("unknown backend `{}` (this build includes: {})", name, names.join(", "))
+    })
+}
+
+pub fn default_backend() -> Box<dyn Backend> {
+    backends().remove(0)
+}
+
+pub fn motor_count(notes: &[NoteInfo]) -> usize {
+    notes.iter().map(|n| n.motor_id as usize + 1).max().unwrap_or(0)
+}
+
+/// Make audio motors for all of the motors that a song uses, which sound like
+/// the machine's motors. Motors that the machine doesn't have are square waves.
+fn audio_motors(machine: &Machine, notes: &[NoteInfo]) -> Vec<SimpleAudioMotor> {
+    (0..motor_count(notes)).map(|motor_id| {
+        let pulse: Pulse = machine.motors.get(motor_id).map_or(Pulse::default(), |m| m.pulse());
+        SimpleAudioMotor::with_pulse(pulse)
+    }).collect()
+}
+
+#[cfg(feature = "raspi")]
+pub struct GpioBackend { }
+
+#[cfg(feature = "raspi")]
+impl Backend for GpioBackend {
+    fn name(&self) -> &'static str { "gpio" }
+
+    fn description(&self) -> &'static str { "stepper motors on the Raspberry Pi's GPIO pins" }
+
+    fn supports_control(&self) -> bool { true }
+
+    fn play(
+        &self,
+        machine: &Machine,
+        notes: Vec<NoteInfo>,
+        voices: Vec<Voice>,
+        control: &PlaybackControl,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut pins: Vec<Box<dyn Motor>> = machine.gpio_motors()?;
+
+        let mut timer: NixTimer = NixTimer::new();
+
+        println!("Playing...");
+        play_note_info_array_with_control(pins.iter_mut().collect(), notes, voices, &mut timer, control)?;
+
+        // Parking the motors can take a while, so it's skipped when ambrose
+        // has been interrupted.
+        if !shutdown::was_interrupted() {
+            for pin in &mut pins {
+                pin.park();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct AudioBackend { }
+
+impl Backend for AudioBackend {
+    fn name(&self) -> &'static str { "audio" }
+
+    fn description(&self) -> &'static str { "a square wave through the speakers" }
+
+    fn supports_control(&self) -> bool { cfg!(feature = "rodio") }
+
+    #[cfg(feature = "rodio")]
+    fn play(
+        &self,
+        machine: &Machine,
+        notes: Vec<NoteInfo>,
+        voices: Vec<Voice>,
+        control: &PlaybackControl,
+    ) -> Result<(), Box<dyn Error>> {
+        let (_stream, stream_handle) = OutputStream::try_default()?;
+        let sink: Sink = Sink::try_new(&stream_handle)?;
+
+        let (sender, samples) = sample_stream(SAMPLE_RATE);
+        sink.append(samples);
+
+        let pins: Vec<SimpleAudioMotor> = audio_motors(machine, &notes);
+        let mut timer: SimpleAudioTimer = SimpleAudioTimer::streaming(SAMPLE_RATE, &pins, sender);
+
+        println!("Playing...");
+        play_note_info_array_with_control(pins, notes, voices, &mut timer, control)?;
+        timer.flush()?;
+
+        // Dropping the timer ends the stream once everything sent so far has
+        // been played.
+        drop(timer);
+        sink.sleep_until_end();
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "rodio"))]
+    fn play(
+        &self,
+        _machine: &Machine,
+        _notes: Vec<NoteInfo>,
+        _voices: Vec<Voice>,
+        _control: &PlaybackControl,
+    ) -> Result<(), Box<dyn Error>> {
+        println!("No way to play this. Try running with --features rodio, or use `ambrose render`.");
+
+        Ok(())
+    }
+}
+
+/// Render a song as audio samples at `SAMPLE_RATE`, as it would sound on the
+/// given machine.
+pub fn render_audio(machine: &Machine, notes: Vec<NoteInfo>, voices: Vec<Voice>) -> Result<Vec<f32>, Box<dyn Error>> {
+    let pins: Vec<SimpleAudioMotor> = audio_motors(machine, &notes);
+
+    let mut timer: SimpleAudioTimer = SimpleAudioTimer::new(SAMPLE_RATE, &pins);
+
+    play_note_info_array(pins, notes, voices, &mut timer)?;
+    timer.flush()?;
+
+    Ok(timer.data)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::*;
+
+    #[test]
+    fn can_find_backends() {
+        assert_eq!(find_backend("audio").unwrap().name(), "audio");
+        assert!(find_backend("floppy").is_err());
+        assert_eq!(default_backend().name(), backends()[0].name());
+    }
+}
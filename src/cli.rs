@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+
+use ambrose::fit::FitMode;
+use ambrose::motor::MAX_MICROSTEPS;
+use ambrose::playback::PlaybackOptions;
+use ambrose::wav::SampleFormat;
+
+pub const USAGE: &str = "\
+Usage:
+    ambrose play <song> [options]
+    ambrose render <song> --out <file> [options]
+    ambrose list
+    ambrose backends
+
+<song> is the name of a built-in song (see `ambrose list`), the path of a song
+file, or the path of a MIDI file (ending in .mid or .midi).
+
+`play` plays the song. `render` renders the song as audio and writes it to a
+WAV file (mono, 44100 Hz). `list` lists the built-in songs, and `backends`
+lists the backends that this build includes.
+
+Options:
+    --backend <backend>  Play through the given backend, such as `gpio` (the
+                         motors) or `audio` (the speakers). The default is the
+                         first one listed by `ambrose backends`. Only for
+                         `play`.
+    --machine <file>     Read the description of the motors from the given
+                         machine file, instead of using two motors on GPIO
+                         pins 15 and 14. Notes outside a motor's frequency
+                         range are moved into it by octaves.
+    --fit <mode>         How to fit notes into the motors' frequency ranges:
+                         `notes` moves each note that's out of range on its
+                         own, `voices` first transposes all of each motor's
+                         notes together, and `off` leaves them alone. The
+                         default is `notes`.
+    --tempo <percent>    Play at the given percentage of the song's tempo, so
+                         that, for example, `--tempo 200` plays twice as fast.
+    --motors <list>      Play each voice on the given motor, as a list of motor
+                         numbers separated by commas. For example,
+                         `--motors 1,0` plays voice 0 on motor 1 and voice 1 on
+                         motor 0. Voices past the end of the list are played
+                         on their own motors.
+    --microsteps <list>  Play each voice in the given microstep mode, as a list
+                         of numbers of microsteps per step separated by
+                         commas, on motors that can switch modes. A single
+                         number sets the mode for every voice.
+    --mute <list>        Leave the given voices silent, as a list of voice
+                         numbers separated by commas.
+    --solo <list>        Only play the given voices, as a list of voice numbers
+                         separated by commas.
+    --arrange <count>    Arrange all of the notes in a MIDI file for the given
+                         number of motors, instead of playing each track and
+                         channel as its own voice. When there are more notes
+                         than motors, the lowest and highest notes are kept.
+    --channels <list>    Play each channel of a MIDI file as the given voice,
+                         as a list of `channel:voice` pairs separated by
+                         commas, with channels numbered from 1. For example,
+                         `--channels 1:0,2:0,3:1` plays channels 1 and 2 as
+                         voice 0 and channel 3 as voice 1. Channels that
+                         aren't listed are left out.
+    --out <file>         The WAV file to write. Only for `render`.
+    --format <format>    Write `pcm` (16-bit) or `float` (32-bit floating-point)
+                         samples. The default is `pcm`. Only for `render`.
+";
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub song: String,
+    pub backend: Option<String>,
+    pub machine: Option<String>,
+    pub tempo_percent: u64,
+    pub playback: PlaybackOptions,
+    pub arrange: Option<usize>,
+    pub channels: Option<HashMap<u8, u8>>,
+    pub fit: Option<FitMode>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RenderOptions {
+    pub out: String,
+    pub format: SampleFormat,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Play(Options),
+    Render(Options, RenderOptions),
+    List,
+    Backends,
+    Help,
+}
+
+fn parse_tempo(value: &str) -> Result<u64, String> {
+    match value.parse() {
+        Ok(percent) if percent > 0 => Ok(percent),
+        _ => Err(format!("`{}` is not a tempo percentage", value)),
+    }
+}
+
+fn parse_arrange(value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(count) if count > 0 && count <= u8::MAX as usize + 1 => Ok(count),
+        _ => Err(format!("`{}` is not a number of motors", value)),
+    }
+}
+
+fn parse_channels(value: &str) -> Result<HashMap<u8, u8>, String> {
+    value.split(',')
+        .map(|pair| {
+            let error = || format!("`{}` is not a channel and a voice, like `1:0`", pair);
+            let (channel, voice) = pair.trim().split_once(':').ok_or_else(error)?;
+
+            match (channel.parse::<u8>(), voice.parse::<u8>()) {
+                (Ok(channel), Ok(voice)) if (1..=16).contains(&channel) => Ok((channel - 1, voice)),
+                _ => Err(error()),
+            }
+        })
+        .collect()
+}
+
+fn parse_fit(value: &str) -> Result<Option<FitMode>, String> {
+    match value {
+        "notes" => Ok(Some(FitMode::Notes)),
+        "voices" => Ok(Some(FitMode::Voices)),
+        "off" => Ok(None),
+        _ => Err(format!("unknown fit mode `{}` (expected `notes`, `voices` or `off`)", value)),
+    }
+}
+
+fn parse_format(value: &str) -> Result<SampleFormat, String> {
+    match value {
+        "pcm" => Ok(SampleFormat::Pcm16),
+        "float" => Ok(SampleFormat::Float32),
+        _ => Err(format!("unknown sample format `{}` (expected `pcm` or `float`)", value)),
+    }
+}
+
+fn parse_motors(value: &str) -> Result<Vec<Option<u8>>, String> {
+    value.split(',')
+        .map(|motor| motor.trim().parse().map(Some).map_err(|_| format!("`{}` is not a motor number", motor)))
+        .collect()
+}
+
+fn parse_microsteps(value: &str) -> Result<Vec<Option<u32>>, String> {
+    value.split(',')
+        .map(|microsteps| match microsteps.trim().parse::<u32>() {
+            Ok(count) if count.is_power_of_two() && count <= MAX_MICROSTEPS => Ok(Some(count)),
+            _ => Err(format!("`{}` is not a number of microsteps", microsteps)),
+        })
+        .collect()
+}
+
+fn parse_voices(value: &str) -> Result<Vec<usize>, String> {
+    value.split(',')
+        .map(|voice| voice.trim().parse().map_err(|_| format!("`{}` is not a voice number", voice)))
+        .collect()
+}
+
+/// Parse the command-line arguments (not including the program name).
+pub fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+    let command: String = match args.next() {
+        Some(command) => command,
+        None => return Ok(Command::Help),
+    };
+
+    match command.as_str() {
+        "play" | "render" => { }
+        "list" | "backends" => return match args.next() {
+            None if command == "list" => Ok(Command::List),
+            None => Ok(Command::Backends),
+            Some(arg) => Err(format!("unexpected argument `{}`", arg)),
+        },
+        "help" | "--help" | "-h" => return Ok(Command::Help),
+        _ => return Err(format!("unknown command `{}`", command)),
+    }
+
+    let mut song: Option<String> = None;
+    let mut backend: Option<String> = None;
+    let mut machine: Option<String> = None;
+    let mut tempo_percent: u64 = 100;
+    let mut playback: PlaybackOptions = PlaybackOptions::default();
+    let mut arrange: Option<usize> = None;
+    let mut channels: Option<HashMap<u8, u8>> = None;
+    let mut fit: Option<FitMode> = Some(FitMode::Notes);
+    let mut out: Option<String> = None;
+    let mut format: SampleFormat = SampleFormat::Pcm16;
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            if song.is_some() {
+                return Err(format!("unexpected argument `{}`", arg));
+            }
+
+            song = Some(arg);
+            continue;
+        }
+
+        let value: String = args.next().ok_or_else(|| format!("{} needs a value", arg))?;
+
+        match arg.as_str() {
+            "--backend" if command == "play" => backend = Some(value),
+            "--machine" => machine = Some(value),
+            "--tempo" => tempo_percent = parse_tempo(&value)?,
+            "--motors" => playback.motors = parse_motors(&value)?,
+            "--microsteps" => playback.microsteps = parse_microsteps(&value)?,
+            "--mute" => playback.muted.extend(parse_voices(&value)?),
+            "--solo" => playback.soloed.extend(parse_voices(&value)?),
+            "--arrange" => arrange = Some(parse_arrange(&value)?),
+            "--channels" => channels = Some(parse_channels(&value)?),
+            "--fit" => fit = parse_fit(&value)?,
+            "--out" if command == "render" => out = Some(value),
+            "--format" if command == "render" => format = parse_format(&value)?,
+            _ => return Err(format!("unknown option `{}` for `{}`", arg, command)),
+        }
+    }
+
+    let song: String = song.ok_or_else(|| format!("`{}` needs a song", command))?;
+    let options: Options = Options { song, backend, machine, tempo_percent, playback, arrange, channels, fit };
+
+    if command == "play" {
+        Ok(Command::Play(options))
+    } else {
+        let out: String = out.ok_or_else(|| "`render` needs an --out file".to_string())?;
+        Ok(Command::Render(options, RenderOptions { out, format }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli::*;
+
+    fn parse(args: &str) -> Result<Command, String> {
+        parse_args(args.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn can_parse_commands() {
+        assert_eq!(parse(""), Ok(Command::Help));
+        assert_eq!(parse("list"), Ok(Command::List));
+        assert_eq!(parse("backends"), Ok(Command::Backends));
+
+        assert_eq!(parse("play hallelujah --backend audio --machine pi.toml --tempo 150 --motors 1,0 --mute 2 --solo 0,1 --microsteps 8,1"), Ok(Command::Play(Options {
+            song: "hallelujah".to_string(),
+            backend: Some("audio".to_string()),
+            machine: Some("pi.toml".to_string()),
+            tempo_percent: 150,
+            playback: PlaybackOptions {
+                muted: vec![2],
+                soloed: vec![0, 1],
+                motors: vec![Some(1), Some(0)],
+                microsteps: vec![Some(8), Some(1)],
+            },
+            arrange: None,
+            channels: None,
+            fit: Some(FitMode::Notes),
+        })));
+
+        assert_eq!(parse("render song.mid --out song.wav --format float --arrange 3 --fit off"), Ok(Command::Render(Options {
+            song: "song.mid".to_string(),
+            backend: None,
+            machine: None,
+            tempo_percent: 100,
+            playback: PlaybackOptions::default(),
+            arrange: Some(3),
+            channels: None,
+            fit: None,
+        }, RenderOptions {
+            out: "song.wav".to_string(),
+            format: SampleFormat::Float32,
+        })));
+
+        let channels: HashMap<u8, u8> = vec![(0, 0), (1, 0), (9, 1)].into_iter().collect();
+        assert_eq!(parse("play song.mid --channels 1:0,2:0,10:1"), Ok(Command::Play(Options {
+            song: "song.mid".to_string(),
+            backend: None,
+            machine: None,
+            tempo_percent: 100,
+            playback: PlaybackOptions::default(),
+            arrange: None,
+            channels: Some(channels),
+            fit: Some(FitMode::Notes),
+        })));
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(parse("dance").is_err());
+        assert!(parse("play").is_err());
+        assert!(parse("play a b").is_err());
+        assert!(parse("play a --tempo 0").is_err());
+        assert!(parse("list a").is_err());
+        assert!(parse("play a --out song.wav").is_err());
+        assert!(parse("render a").is_err());
+        assert!(parse("render a --backend gpio --out song.wav").is_err());
+        assert!(parse("render a --out song.wav --format mp3").is_err());
+        assert!(parse("play a --arrange 0").is_err());
+        assert!(parse("play a --fit squash").is_err());
+        assert!(parse("play a --solo bass").is_err());
+        assert!(parse("play a --microsteps 3").is_err());
+        assert!(parse("play a --channels 0:1").is_err());
+        assert!(parse("play a --channels 1").is_err());
+    }
+}
@@ -0,0 +1,135 @@
+use std::sync::{
+    Arc,
+    Condvar,
+    Mutex,
+    MutexGuard,
+    atomic::AtomicBool,
+    atomic::AtomicU64,
+    atomic::Ordering,
+};
+
+/// A handle for controlling a song while it's playing. Clones of the handle
+/// can be sent to other threads, and they all control the same song.
+#[derive(Clone)]
+pub struct PlaybackControl {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<ControlState>,
+    changed: Condvar,
+    /// Set whenever a request is made, so that the engine only needs to check
+    /// one flag between step edges.
+    pending: AtomicBool,
+    position_mcs: AtomicU64,
+}
+
+#[derive(Default)]
+struct ControlState {
+    paused: bool,
+    stopped: bool,
+    seek_mcs: Option<u64>,
+}
+
+/// What the engine should do after checking for requests.
+pub enum ControlAction {
+    Continue,
+    Seek(u64),
+    Stop,
+}
+
+impl PlaybackControl {
+    pub fn new() -> Self {
+        PlaybackControl {
+            shared: Arc::new(Shared {
+                state: Mutex::new(ControlState::default()),
+                changed: Condvar::new(),
+                pending: AtomicBool::new(false),
+                position_mcs: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    fn request<F: FnOnce(&mut ControlState)>(&self, f: F) {
+        let mut state: MutexGuard<ControlState> = self.shared.state.lock().unwrap();
+        f(&mut state);
+        self.shared.pending.store(true, Ordering::Release);
+        self.shared.changed.notify_all();
+    }
+
+    /// Pause the song, with all of the motors reset, until `resume` is
+    /// called.
+    pub fn pause(&self) {
+        self.request(|state| state.paused = true);
+    }
+
+    pub fn resume(&self) {
+        self.request(|state| state.paused = false);
+    }
+
+    /// Stop the song, with all of the motors reset. A stopped song can't be
+    /// resumed.
+    pub fn stop(&self) {
+        self.request(|state| state.stopped = true);
+    }
+
+    /// Jump to the given time in the song, in microseconds from the start.
+    pub fn seek(&self, time_mcs: u64) {
+        self.request(|state| state.seek_mcs = Some(time_mcs));
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.shared.state.lock().unwrap().paused
+    }
+
+    /// How far into the song playback has got, in microseconds.
+    pub fn position_mcs(&self) -> u64 {
+        self.shared.position_mcs.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_position_mcs(&self, position_mcs: u64) {
+        self.shared.position_mcs.store(position_mcs, Ordering::Relaxed);
+    }
+
+    /// Whether any requests have been made since the engine last checked.
+    pub(crate) fn has_pending(&self) -> bool {
+        self.shared.pending.load(Ordering::Acquire)
+    }
+
+    /// Handle the requests that have been made. If the song is paused, this
+    /// calls `on_pause` and then blocks until the song is resumed, stopped or
+    /// sought.
+    pub(crate) fn take_action<F: FnOnce()>(&self, on_pause: F) -> ControlAction {
+        let mut state: MutexGuard<ControlState> = self.shared.state.lock().unwrap();
+        self.shared.pending.store(false, Ordering::Release);
+
+        if state.paused && !state.stopped && state.seek_mcs.is_none() {
+            on_pause();
+
+            while state.paused && !state.stopped && state.seek_mcs.is_none() {
+                state = self.shared.changed.wait(state).unwrap();
+            }
+
+            self.shared.pending.store(false, Ordering::Release);
+        }
+
+        if state.stopped {
+            ControlAction::Stop
+        } else if let Some(time_mcs) = state.seek_mcs.take() {
+            // If the song was paused, it should stay paused after the seek.
+            if state.paused {
+                self.shared.pending.store(true, Ordering::Release);
+            }
+
+            ControlAction::Seek(time_mcs)
+        } else {
+            ControlAction::Continue
+        }
+    }
+}
+
+impl Default for PlaybackControl {
+    fn default() -> Self {
+        PlaybackControl::new()
+    }
+}
@@ -0,0 +1,195 @@
+use crate::notes::NoteInfo;
+
+// Motors only sound good within a limited range of frequencies, and above it
+// they can stall. Fitting a song moves the notes that are out of their motor's
+// range into it, by whole octaves so that they're still the same notes.
+//
+// Each note can be moved on its own, which keeps as much of the song as
+// possible where it was. Alternatively, each motor's notes can first be
+// transposed together, by however many octaves fits the most of them into
+// the range, which keeps the shape of the line, and then any notes that are
+// still out of range are moved on their own.
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrequencyRange {
+    pub min_hz: Option<f64>,
+    pub max_hz: Option<f64>,
+}
+
+impl FrequencyRange {
+    // `is_none_or` would need Rust 1.82.
+    #[allow(unknown_lints, clippy::unnecessary_map_or)]
+    fn contains(&self, frequency_mchz: u64) -> bool {
+        let frequency_hz: f64 = frequency_mchz as f64 / 1_000_000.0;
+
+        self.min_hz.map_or(true, |min| frequency_hz >= min) && self.max_hz.map_or(true, |max| frequency_hz <= max)
+    }
+
+    fn is_below(&self, frequency_mchz: u64) -> bool {
+        self.min_hz.is_some_and(|min| (frequency_mchz as f64 / 1_000_000.0) < min)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FitMode {
+    /// Move each out-of-range note by octaves on its own.
+    Notes,
+    /// Transpose each motor's notes together first.
+    Voices,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FitReport {
+    /// How many octaves each motor's notes were transposed by, for the motors
+    /// whose notes were transposed.
+    pub transposed: Vec<(u8, i32)>,
+    /// The indices of the notes that were moved by octaves on their own.
+    pub moved: Vec<u32>,
+    /// The indices of the notes that couldn't be moved into their motor's
+    /// range, because the range is less than an octave wide.
+    pub unfit: Vec<u32>,
+}
+
+/// Move a frequency by the given number of octaves.
+fn transpose(frequency_mchz: u64, octaves: i32) -> u64 {
+    if octaves >= 0 {
+        frequency_mchz << octaves
+    } else {
+        frequency_mchz >> -octaves
+    }
+}
+
+/// Move a frequency by octaves until it's in the range, if it can be.
+fn fold(frequency_mchz: u64, range: &FrequencyRange) -> Option<u64> {
+    let mut octaves: i32 = 0;
+    let step: i32 = if range.is_below(frequency_mchz) { 1 } else { -1 };
+
+    // A frequency can't be moved more than about 40 octaves either way
+    // before it runs out of bits.
+    while octaves.abs() < 40 {
+        let moved_mchz: u64 = transpose(frequency_mchz, octaves);
+
+        if moved_mchz == 0 {
+            return None;
+        }
+
+        if range.contains(moved_mchz) {
+            return Some(moved_mchz);
+        }
+
+        if (step == 1) != range.is_below(moved_mchz) {
+            return None;
+        }
+
+        octaves += step;
+    }
+
+    None
+}
+
+/// Fit the notes played on each motor into the motor's range, given the range
+/// of each motor in order of motor number. Motors without a range, and rests,
+/// are left alone.
+pub fn fit_song(notes: &mut [NoteInfo], ranges: &[FrequencyRange], mode: FitMode) -> FitReport {
+    let mut report: FitReport = FitReport::default();
+
+    let played = |note: &NoteInfo| note.frequency_mchz > 0 && !note.exit;
+
+    if mode == FitMode::Voices {
+        for (motor_id, range) in ranges.iter().enumerate() {
+            let motor_notes: Vec<u64> = notes.iter()
+                .filter(|n| played(n) && n.motor_id as usize == motor_id)
+                .map(|n| n.frequency_mchz)
+                .collect();
+
+            let fitting_notes = |octaves: i32| motor_notes.iter()
+                .filter(|&&frequency_mchz| range.contains(transpose(frequency_mchz, octaves)))
+                .count();
+
+            // On a tie, the smallest transposition wins.
+            let octaves: i32 = (-8..=8)
+                .max_by_key(|&octaves: &i32| (fitting_notes(octaves), -octaves.abs()))
+                .unwrap();
+
+            if octaves != 0 {
+                report.transposed.push((motor_id as u8, octaves));
+
+                for note in notes.iter_mut().filter(|n| played(n) && n.motor_id as usize == motor_id) {
+                    note.frequency_mchz = transpose(note.frequency_mchz, octaves);
+                }
+            }
+        }
+    }
+
+    for (index, note) in notes.iter_mut().enumerate() {
+        let range: FrequencyRange = match ranges.get(note.motor_id as usize) {
+            Some(range) if played(note) && !range.contains(note.frequency_mchz) => *range,
+            _ => continue,
+        };
+
+        match fold(note.frequency_mchz, &range) {
+            Some(frequency_mchz) => {
+                note.frequency_mchz = frequency_mchz;
+                report.moved.push(index as u32);
+            }
+            None => report.unfit.push(index as u32),
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fit::*;
+
+    fn note(motor_id: u8, frequency_hz: u64) -> NoteInfo {
+        NoteInfo {
+            next_note_index: 0,
+            motor_id,
+            exit: false,
+            stop: false,
+            frequency_mchz: frequency_hz * 1_000_000,
+            length_mcs: 1000,
+            rearticulate: true,
+            repeat: None,
+        }
+    }
+
+    fn frequencies_hz(notes: &[NoteInfo]) -> Vec<u64> {
+        notes.iter().map(|n| n.frequency_mchz / 1_000_000).collect()
+    }
+
+    fn range(min_hz: f64, max_hz: f64) -> FrequencyRange {
+        FrequencyRange { min_hz: Some(min_hz), max_hz: Some(max_hz) }
+    }
+
+    #[test]
+    fn moves_notes_by_octaves() {
+        let mut notes: Vec<NoteInfo> = vec![note(0, 50), note(0, 200), note(0, 1800), note(0, 0), note(1, 50)];
+        let report: FitReport = fit_song(&mut notes, &[range(100.0, 1000.0)], FitMode::Notes);
+
+        assert_eq!(frequencies_hz(&notes), vec![100, 200, 900, 0, 50]);
+        assert_eq!(report, FitReport { transposed: vec![], moved: vec![0, 2], unfit: vec![] });
+    }
+
+    #[test]
+    fn transposes_voices() {
+        let mut notes: Vec<NoteInfo> = vec![note(0, 1600), note(0, 1800), note(0, 500), note(1, 300)];
+        let report: FitReport = fit_song(&mut notes, &[range(300.0, 1000.0)], FitMode::Voices);
+
+        // Two of motor 0's notes fit when it's transposed down an octave, and
+        // the third is moved back up on its own.
+        assert_eq!(frequencies_hz(&notes), vec![800, 900, 500, 300]);
+        assert_eq!(report, FitReport { transposed: vec![(0, -1)], moved: vec![2], unfit: vec![] });
+    }
+
+    #[test]
+    fn reports_notes_that_dont_fit() {
+        let mut notes: Vec<NoteInfo> = vec![note(0, 400), note(0, 300)];
+        let report: FitReport = fit_song(&mut notes, &[range(420.0, 600.0)], FitMode::Notes);
+
+        assert_eq!(frequencies_hz(&notes), vec![400, 600]);
+        assert_eq!(report.unfit, vec![0]);
+    }
+}
@@ -0,0 +1,37 @@
+//! ambrose plays music using stepper motors.
+//!
+//! Songs are graphs of `NoteInfo`s, usually built with a `SongBuilder` or read
+//! with `songreader` or `midireader`, and they're played by
+//! `notes::play_note_info_array` on anything that implements the `Motor` and
+//! `Timer` traits.
+
+pub mod arranger;
+pub mod backend;
+pub mod control;
+pub mod fit;
+pub mod machine;
+pub mod midireader;
+pub mod motor;
+pub mod notes;
+pub mod pitch;
+pub mod playback;
+pub mod shutdown;
+pub mod songbuilder;
+pub mod songreader;
+pub mod songs;
+pub mod stream;
+pub mod tempo;
+pub mod timer;
+pub mod validate;
+pub mod wav;
+
+pub use crate::motor::Motor;
+pub use crate::notes::{
+    NoteInfo,
+    Repeat,
+    Voice,
+    play_note_info_array,
+    voice,
+};
+pub use crate::songbuilder::SongBuilder;
+pub use crate::timer::Timer;
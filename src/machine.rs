@@ -0,0 +1,467 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+
+use serde::Deserialize;
+
+use crate::fit::FrequencyRange;
+
+#[cfg(feature = "raspi")]
+use crate::motor::{
+    Motor,
+    coil_motor,
+    enabled_motor,
+    floppy_motor,
+    gpio_motor,
+    microstep_motor,
+    step_dir_motor,
+};
+use crate::motor::{
+    CoilSequence,
+    MAX_MICROSTEPS,
+    MicrostepDriver,
+    Pulse,
+};
+
+// A machine file is a TOML file describing the motors that ambrose can play
+// on, in order of motor number. For example:
+//
+//     [[motor]]
+//     name = "melody"
+//     kind = "step"
+//     pin = 15
+//     min_frequency_hz = 30
+//     max_frequency_hz = 1000
+//
+//     [[motor]]
+//     name = "bass"
+//     kind = "step"
+//     pin = 14
+//     pulse_width_mcs = 5
+//
+// The frequency range is optional. Notes outside it are moved into it by
+// octaves (see the fit module). Motors are stepped with a square wave unless
+// they have either a `duty_percent`, for the percentage of each cycle that the
+// pin is high, or a `pulse_width_mcs`, for a fixed length of time that the pin
+// is high for each step.
+//
+// A `step_dir` motor has a driver with a direction pin as well, and it turns
+// around before it reaches either end of its travel:
+//
+//     [[motor]]
+//     name = "carriage"
+//     kind = "step_dir"
+//     step_pin = 15
+//     dir_pin = 18
+//     travel_steps = 800
+//
+// A `floppy` motor is a floppy drive, played by moving its head between
+// tracks. Its head is moved back to track 0 when it's set up, and the drive
+// select pin is optional, for drives that are jumpered to be always selected:
+//
+//     [[motor]]
+//     name = "floppy"
+//     kind = "floppy"
+//     step_pin = 15
+//     dir_pin = 18
+//     select_pin = 23
+//     tracks = 80
+//
+// A `coils` motor has no step driver, and its four coils are switched directly,
+// in the given sequence (`wave`, `full` or `half`, which is `full` by default):
+//
+//     [[motor]]
+//     name = "28byj"
+//     kind = "coils"
+//     coil_pins = [5, 6, 13, 19]
+//     sequence = "half"
+//
+// Its coils are switched off once it has been idle for `idle_timeout_ms`,
+// whether or not it has an enable pin.
+//
+// Any motor can have an `enable_pin`, for a driver with an active-low ENABLE
+// input. The driver is only enabled while the motor has notes to play, and
+// it's disabled once the motor has been idle for `idle_timeout_ms`.
+//
+// A motor whose driver takes microsteps says how many it takes per step, so
+// that ambrose can step it faster to play the same notes. If its mode pins are
+// wired up too, voices can switch it to other modes:
+//
+//     [[motor]]
+//     name = "melody"
+//     kind = "step"
+//     pin = 15
+//     microsteps = 4
+//     microstep_pins = [5, 6, 13]
+//     microstep_driver = "drv8825"
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MotorKind {
+    /// A motor whose driver is stepped by a single GPIO pin.
+    Step { pin: u8 },
+    /// A motor whose driver has a STEP pin and a DIR pin, moving a carriage
+    /// that can travel the given number of steps from end to end (by default,
+    /// `DEFAULT_TRAVEL_STEPS`). The carriage has to start in the middle.
+    StepDir { step_pin: u8, dir_pin: u8, travel_steps: Option<u64> },
+    /// A floppy drive, with its STEP, DIR and (optionally) drive select lines
+    /// on GPIO pins, and the given number of tracks (by default,
+    /// `DEFAULT_TRACKS`).
+    Floppy { step_pin: u8, dir_pin: u8, select_pin: Option<u8>, tracks: Option<u64> },
+    /// A motor whose four coils are switched by GPIO pins, in the order that
+    /// they're energized in, with the given sequence (by default, a full-step
+    /// sequence).
+    Coils { coil_pins: Vec<u8>, sequence: Option<CoilSequence> },
+}
+
+/// How far a STEP/DIR motor travels if its machine file doesn't say, which is
+/// one turn of a typical motor.
+pub const DEFAULT_TRAVEL_STEPS: u64 = 200;
+
+/// How many tracks a floppy drive has if its machine file doesn't say, which
+/// is right for most 3.5" drives.
+pub const DEFAULT_TRACKS: u64 = 80;
+
+/// How long a motor with an enable pin can be idle before it's disabled, if
+/// its machine file doesn't say.
+pub const DEFAULT_IDLE_TIMEOUT_MS: u64 = 500;
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct MotorConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: MotorKind,
+    pub min_frequency_hz: Option<f64>,
+    pub max_frequency_hz: Option<f64>,
+    pub duty_percent: Option<f64>,
+    pub pulse_width_mcs: Option<u64>,
+    pub enable_pin: Option<u8>,
+    pub idle_timeout_ms: Option<u64>,
+    pub microsteps: Option<u32>,
+    pub microstep_pins: Option<Vec<u8>>,
+    pub microstep_driver: Option<MicrostepDriver>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Machine {
+    #[serde(rename = "motor")]
+    pub motors: Vec<MotorConfig>,
+}
+
+impl MotorConfig {
+    /// Put a motor into this motor's microstep mode, if it has one.
+    #[cfg(feature = "raspi")]
+    fn microstepped<M: Motor + 'static>(&self, motor: M) -> Result<Box<dyn Motor>, Box<dyn Error>> {
+        Ok(match (self.microsteps, &self.microstep_pins) {
+            (None, None) => Box::new(motor),
+            (microsteps, pins) => Box::new(microstep_motor(
+                motor,
+                self.microstep_driver.unwrap_or(MicrostepDriver::A4988),
+                pins.as_deref().unwrap_or(&[]),
+                microsteps.unwrap_or(1),
+            )?),
+        })
+    }
+
+    pub fn pins(&self) -> Vec<u8> {
+        let pins: Vec<u8> = match &self.kind {
+            MotorKind::Step { pin } => vec![*pin],
+            MotorKind::StepDir { step_pin, dir_pin, .. } => vec![*step_pin, *dir_pin],
+            MotorKind::Floppy { step_pin, dir_pin, select_pin, .. } =>
+                vec![*step_pin, *dir_pin].into_iter().chain(*select_pin).collect(),
+            MotorKind::Coils { coil_pins, .. } => coil_pins.clone(),
+        };
+
+        pins.into_iter()
+            .chain(self.enable_pin)
+            .chain(self.microstep_pins.iter().flatten().copied())
+            .collect()
+    }
+
+    pub fn frequency_range(&self) -> FrequencyRange {
+        FrequencyRange { min_hz: self.min_frequency_hz, max_hz: self.max_frequency_hz }
+    }
+
+    pub fn pulse(&self) -> Pulse {
+        match (self.duty_percent, self.pulse_width_mcs) {
+            (_, Some(mcs)) => Pulse::Width { mcs },
+            (Some(percent), None) => Pulse::Duty { percent },
+            (None, None) => Pulse::default(),
+        }
+    }
+}
+
+impl Default for Machine {
+    /// The original two-motor machine, with motors on pins 15 and 14.
+    fn default() -> Self {
+        let step_motor = |name: &str, pin: u8| MotorConfig {
+            name: name.to_string(),
+            kind: MotorKind::Step { pin },
+            min_frequency_hz: None,
+            max_frequency_hz: None,
+            duty_percent: None,
+            pulse_width_mcs: None,
+            enable_pin: None,
+            idle_timeout_ms: None,
+            microsteps: None,
+            microstep_pins: None,
+            microstep_driver: None,
+        };
+
+        Machine { motors: vec![step_motor("motor 0", 15), step_motor("motor 1", 14)] }
+    }
+}
+
+impl Machine {
+    /// Read a machine description from the text of a machine file.
+    pub fn parse(text: &str) -> Result<Machine, Box<dyn Error>> {
+        let machine: Machine = toml::from_str(text)?;
+        machine.check()?;
+        Ok(machine)
+    }
+
+    /// Read a machine description from a machine file.
+    pub fn read_file(path: &str) -> Result<Machine, Box<dyn Error>> {
+        let text: String = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Ok(Machine::parse(&text).map_err(|e| format!("{}: {}", path, e))?)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if self.motors.is_empty() {
+            return Err("the machine doesn't have any motors".to_string());
+        }
+
+        let mut names: HashSet<&str> = HashSet::new();
+        let mut pins: HashSet<u8> = HashSet::new();
+
+        for motor in &self.motors {
+            if !names.insert(&motor.name) {
+                return Err(format!("there's more than one motor named `{}`", motor.name));
+            }
+
+            for pin in motor.pins() {
+                if !pins.insert(pin) {
+                    return Err(format!("pin {} is used more than once (by motor `{}`)", pin, motor.name));
+                }
+            }
+
+            if let (Some(min), Some(max)) = (motor.min_frequency_hz, motor.max_frequency_hz) {
+                if min > max {
+                    return Err(format!("motor `{}` has a minimum frequency above its maximum", motor.name));
+                }
+            }
+
+            if motor.duty_percent.is_some() && motor.pulse_width_mcs.is_some() {
+                return Err(format!("motor `{}` has both a duty cycle and a pulse width", motor.name));
+            }
+
+            if motor.duty_percent.is_some_and(|percent| !(percent > 0.0 && percent < 100.0)) {
+                return Err(format!("motor `{}` has a duty cycle that isn't between 0 and 100 percent", motor.name));
+            }
+
+            if motor.pulse_width_mcs == Some(0) {
+                return Err(format!("motor `{}` has a pulse width of 0", motor.name));
+            }
+
+            let is_coils: bool = matches!(motor.kind, MotorKind::Coils { .. });
+
+            if let MotorKind::Coils { coil_pins, .. } = &motor.kind {
+                if coil_pins.len() != 4 {
+                    return Err(format!("motor `{}` needs 4 coil pins", motor.name));
+                }
+            }
+
+            if is_coils && (motor.microsteps.is_some() || motor.microstep_pins.is_some()) {
+                return Err(format!("motor `{}` is stepped by its coil sequence, so it can't take microsteps", motor.name));
+            }
+
+            let is_floppy: bool = matches!(motor.kind, MotorKind::Floppy { .. });
+
+            if is_floppy && (motor.microsteps.is_some() || motor.microstep_pins.is_some()) {
+                return Err(format!("motor `{}` is a floppy drive, which can't take microsteps", motor.name));
+            }
+
+            if motor.idle_timeout_ms.is_some() && motor.enable_pin.is_none() && !is_coils {
+                return Err(format!("motor `{}` has an idle timeout but no enable pin", motor.name));
+            }
+
+            if let Some(microsteps) = motor.microsteps {
+                if !microsteps.is_power_of_two() || microsteps > MAX_MICROSTEPS {
+                    return Err(format!("motor `{}` can't take {} microsteps", motor.name, microsteps));
+                }
+            }
+
+            match (&motor.microstep_pins, motor.microstep_driver) {
+                (Some(pins), _) if pins.len() != 3 => {
+                    return Err(format!("motor `{}` needs 3 microstep pins", motor.name));
+                }
+                (Some(_), driver) => {
+                    let microsteps: u32 = motor.microsteps.unwrap_or(1);
+
+                    if driver.unwrap_or(MicrostepDriver::A4988).mode_pin_levels(microsteps).is_none() {
+                        return Err(format!("motor `{}`'s driver can't take {} microsteps", motor.name, microsteps));
+                    }
+                }
+                (None, Some(_)) => {
+                    return Err(format!("motor `{}` has a microstep driver but no microstep pins", motor.name));
+                }
+                (None, None) => { }
+            }
+
+            if let MotorKind::StepDir { travel_steps: Some(travel_steps), .. } = &motor.kind {
+                if *travel_steps < 2 {
+                    return Err(format!("motor `{}` needs at least 2 steps of travel", motor.name));
+                }
+            }
+
+            if let MotorKind::Floppy { tracks: Some(tracks), .. } = &motor.kind {
+                if *tracks < 2 {
+                    return Err(format!("motor `{}` needs at least 2 tracks", motor.name));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The pulse that each motor is stepped with, in order.
+    pub fn pulses(&self) -> Vec<Pulse> {
+        self.motors.iter().map(|motor| motor.pulse()).collect()
+    }
+
+    /// The frequency range of each motor, in order.
+    pub fn frequency_ranges(&self) -> Vec<FrequencyRange> {
+        self.motors.iter().map(|motor| motor.frequency_range()).collect()
+    }
+
+    /// Set up the GPIO motors described by this machine, in order.
+    #[cfg(feature = "raspi")]
+    pub fn gpio_motors(&self) -> Result<Vec<Box<dyn Motor>>, Box<dyn Error>> {
+        self.motors.iter().map(|motor| -> Result<Box<dyn Motor>, Box<dyn Error>> {
+            let gpio_motor: Box<dyn Motor> = match motor.kind.clone() {
+                MotorKind::Step { pin } => motor.microstepped(gpio_motor(pin, motor.pulse())?)?,
+                // The STEP/DIR motor has to know which mode its driver is in,
+                // to keep track of its carriage.
+                MotorKind::StepDir { step_pin, dir_pin, travel_steps } => Box::new(step_dir_motor(
+                    motor.microstepped(gpio_motor(step_pin, motor.pulse())?)?,
+                    dir_pin,
+                    travel_steps.unwrap_or(DEFAULT_TRAVEL_STEPS),
+                )?),
+                MotorKind::Floppy { step_pin, dir_pin, select_pin, tracks } => Box::new(floppy_motor(
+                    step_pin,
+                    dir_pin,
+                    select_pin,
+                    tracks.unwrap_or(DEFAULT_TRACKS),
+                    motor.pulse(),
+                )?),
+                MotorKind::Coils { coil_pins, sequence } => Box::new(coil_motor(
+                    &coil_pins,
+                    sequence.unwrap_or(CoilSequence::Full),
+                    Some(motor.idle_timeout_ms.unwrap_or(DEFAULT_IDLE_TIMEOUT_MS) * 1000),
+                )?),
+            };
+
+            Ok(match motor.enable_pin {
+                Some(enable_pin) => {
+                    let idle_timeout_ms: u64 = motor.idle_timeout_ms.unwrap_or(DEFAULT_IDLE_TIMEOUT_MS);
+                    Box::new(enabled_motor(gpio_motor, enable_pin, idle_timeout_ms * 1000)?)
+                }
+                None => gpio_motor,
+            })
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::machine::*;
+
+    #[test]
+    fn can_read_machine() {
+        let machine: Machine = Machine::parse("
+            [[motor]]
+            name = 'melody'
+            kind = 'step'
+            pin = 15
+            min_frequency_hz = 30
+            max_frequency_hz = 1000.5
+
+            [[motor]]
+            name = 'bass'
+            kind = 'step'
+            pin = 14
+            pulse_width_mcs = 5
+
+            [[motor]]
+            name = 'carriage'
+            kind = 'step_dir'
+            step_pin = 17
+            dir_pin = 18
+            enable_pin = 19
+            idle_timeout_ms = 2000
+            microsteps = 16
+            microstep_pins = [5, 6, 13]
+            microstep_driver = 'drv8825'
+
+            [[motor]]
+            name = 'floppy'
+            kind = 'floppy'
+            step_pin = 22
+            dir_pin = 23
+            select_pin = 24
+
+            [[motor]]
+            name = '28byj'
+            kind = 'coils'
+            coil_pins = [20, 21, 25, 26]
+            sequence = 'half'
+        ").unwrap();
+
+        assert_eq!(machine.motors.len(), 5);
+        assert_eq!(machine.motors[0].kind, MotorKind::Step { pin: 15 });
+        assert_eq!(machine.motors[0].min_frequency_hz, Some(30.0));
+        assert_eq!(machine.motors[0].max_frequency_hz, Some(1000.5));
+        assert_eq!(machine.motors[1].name, "bass");
+        assert_eq!(machine.motors[1].max_frequency_hz, None);
+        assert_eq!(machine.motors[2].kind, MotorKind::StepDir { step_pin: 17, dir_pin: 18, travel_steps: None });
+        assert_eq!(machine.motors[2].pins(), vec![17, 18, 19, 5, 6, 13]);
+        assert_eq!(machine.motors[2].microstep_driver, Some(MicrostepDriver::Drv8825));
+        assert_eq!(machine.motors[2].idle_timeout_ms, Some(2000));
+        assert_eq!(machine.motors[3].kind, MotorKind::Floppy { step_pin: 22, dir_pin: 23, select_pin: Some(24), tracks: None });
+        assert_eq!(machine.motors[3].pins(), vec![22, 23, 24]);
+        assert_eq!(machine.motors[4].kind, MotorKind::Coils { coil_pins: vec![20, 21, 25, 26], sequence: Some(CoilSequence::Half) });
+        assert_eq!(machine.pulses()[..2], [Pulse::Duty { percent: 50.0 }, Pulse::Width { mcs: 5 }]);
+    }
+
+    #[test]
+    fn rejects_bad_machines() {
+        assert!(Machine::parse("").is_err());
+        assert!(Machine::parse("[[motor]]\nname = 'a'\nkind = 'banjo'\npin = 1").is_err());
+        assert!(Machine::parse("[[motor]]\nname = 'a'\nkind = 'step'\npin = 1\n\
+                                [[motor]]\nname = 'b'\nkind = 'step'\npin = 1").is_err());
+        assert!(Machine::parse("[[motor]]\nname = 'a'\nkind = 'step'\npin = 1\n\
+                                min_frequency_hz = 100\nmax_frequency_hz = 50").is_err());
+        assert!(Machine::parse("[[motor]]\nname = 'a'\nkind = 'step'\npin = 1\nduty_percent = 100").is_err());
+        assert!(Machine::parse("[[motor]]\nname = 'a'\nkind = 'step'\npin = 1\n\
+                                duty_percent = 10\npulse_width_mcs = 5").is_err());
+        assert!(Machine::parse("[[motor]]\nname = 'a'\nkind = 'step_dir'\nstep_pin = 1\ndir_pin = 1").is_err());
+        assert!(Machine::parse("[[motor]]\nname = 'a'\nkind = 'step_dir'\nstep_pin = 1\ndir_pin = 2\n\
+                                travel_steps = 1").is_err());
+        assert!(Machine::parse("[[motor]]\nname = 'a'\nkind = 'floppy'\nstep_pin = 1\ndir_pin = 2\n\
+                                select_pin = 1").is_err());
+        assert!(Machine::parse("[[motor]]\nname = 'a'\nkind = 'floppy'\nstep_pin = 1\ndir_pin = 2\n\
+                                tracks = 1").is_err());
+        assert!(Machine::parse("[[motor]]\nname = 'a'\nkind = 'step'\npin = 1\nenable_pin = 1").is_err());
+        assert!(Machine::parse("[[motor]]\nname = 'a'\nkind = 'step'\npin = 1\nidle_timeout_ms = 100").is_err());
+        assert!(Machine::parse("[[motor]]\nname = 'a'\nkind = 'step'\npin = 1\nmicrosteps = 3").is_err());
+        assert!(Machine::parse("[[motor]]\nname = 'a'\nkind = 'step'\npin = 1\nmicrostep_pins = [2, 3]").is_err());
+        assert!(Machine::parse("[[motor]]\nname = 'a'\nkind = 'step'\npin = 1\nmicrosteps = 32\n\
+                                microstep_pins = [2, 3, 4]").is_err());
+        assert!(Machine::parse("[[motor]]\nname = 'a'\nkind = 'step'\npin = 1\nmicrostep_driver = 'a4988'").is_err());
+        assert!(Machine::parse("[[motor]]\nname = 'a'\nkind = 'coils'\ncoil_pins = [1, 2, 3]").is_err());
+        assert!(Machine::parse("[[motor]]\nname = 'a'\nkind = 'coils'\ncoil_pins = [1, 2, 3, 4]\nsequence = 'micro'").is_err());
+        assert!(Machine::parse("[[motor]]\nname = 'a'\nkind = 'coils'\ncoil_pins = [1, 2, 3, 4]\nmicrosteps = 2").is_err());
+        assert!(Machine::parse("[[motor]]\nname = 'a'\nkind = 'floppy'\nstep_pin = 1\ndir_pin = 2\n\
+                                microstep_pins = [3, 4, 5]").is_err());
+    }
+}
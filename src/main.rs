@@ -1,220 +1,240 @@
+use std::env;
 use std::error::Error;
+use std::io;
+use std::io::BufRead;
+use std::process;
+use std::thread;
 
-#[cfg(feature = "rodio")]
-use rodio::{
-    buffer::SamplesBuffer,
-    OutputStream,
-    Sink,
+mod cli;
+
+use ambrose::arranger::{
+    Arrangement,
+    Arranger,
+};
+
+use ambrose::backend::{
+    Backend,
+    backends,
+    default_backend,
+    find_backend,
+    render_audio,
+    SAMPLE_RATE,
+};
+
+use ambrose::control::PlaybackControl;
+
+use ambrose::fit::{
+    FitReport,
+    fit_song,
 };
 
-mod motor;
-mod notes;
-mod songbuilder;
-mod songs;
-mod timer;
+use ambrose::machine::Machine;
 
-#[cfg(feature = "raspi")]
-use crate::motor::{
-    GpioMotor,
-    gpio_motor,
+use ambrose::midireader::read_midi_notes_file;
+
+use ambrose::notes::NoteInfo;
+use ambrose::notes::Voice;
+
+use ambrose::shutdown;
+
+use ambrose::songs::{
+    is_midi_file,
+    load_song,
+    SONGS,
 };
-#[cfg(not(feature = "raspi"))]
-use crate::motor::SimpleAudioMotor;
-
-use crate::notes::NoteInfo;
-use crate::notes::play_note_info_array;
-use crate::notes::Voice;
-use crate::notes::voice;
-
-use crate::songbuilder::SongBuilder;
-
-use crate::songs::hallelujah;
-
-#[cfg(not(feature = "raspi"))]
-use crate::timer::SimpleAudioTimer;
-#[cfg(feature = "raspi")]
-use crate::timer::NixTimer;
-
-fn note(next_note_index: u32, motor_id: u8, frequency: u32, length: u32) -> NoteInfo {
-    NoteInfo {
-        next_note_index,
-        motor_id,
-        exit: false,
-        frequency_mchz: (frequency as u64) * 10000,
-        length_mcs: (length as u64) * 10000,
-        rearticulate: false,
+
+use ambrose::validate::{
+    check_song,
+    validate_song,
+};
+
+use ambrose::wav::write_wav_file;
+
+use crate::cli::{
+    Command,
+    Options,
+    parse_args,
+    RenderOptions,
+    USAGE,
+};
+
+/// Arrange the notes in a MIDI file for the given number of motors, warning
+/// about any notes that had to be dropped or cut short.
+fn arrange_song(song: &str, motors: usize) -> Result<(Vec<NoteInfo>, Vec<Voice>), Box<dyn Error>> {
+    if !is_midi_file(song) {
+        return Err("--arrange only works with MIDI files".into());
+    }
+
+    let arrangement: Arrangement = Arranger::new(motors).arrange(&read_midi_notes_file(song)?);
+    let cut_count: usize = arrangement.dropped.iter().filter(|d| d.played_mcs > 0).count();
+    let dropped_count: usize = arrangement.dropped.len() - cut_count;
+
+    if !arrangement.dropped.is_empty() {
+        eprintln!("warning: to fit the song onto {} motors, {} notes were left out and {} were cut short",
+            motors, dropped_count, cut_count);
     }
+
+    Ok(arrangement.song.build())
 }
 
-#[cfg(all(not(feature="raspi"), feature = "rodio"))]
-fn play_data(data: Vec<f32>) -> Result<(), Box<dyn Error>> {
-    let (_stream, stream_handle) = OutputStream::try_default()?;
-    let sink: Sink = Sink::try_new(&stream_handle)?;
+fn load_machine(options: &Options) -> Result<Machine, Box<dyn Error>> {
+    match &options.machine {
+        Some(path) => Machine::read_file(path),
+        None => Ok(Machine::default()),
+    }
+}
 
-    let buffer: SamplesBuffer<f32> = SamplesBuffer::new(1, 44100, data);
+/// Load the song given in the options, apply the tempo and the playback
+/// options (muting, soloing, moving voices to other motors and choosing their
+/// microstep modes) to it, fit it into the machine's frequency ranges, and
+/// check it for problems. Problems that don't stop the song from being played
+/// are printed as warnings.
+fn prepare_song(options: &Options, machine: &Machine) -> Result<(Vec<NoteInfo>, Vec<Voice>), Box<dyn Error>> {
+    if options.channels.is_some() && (options.arrange.is_some() || !is_midi_file(&options.song)) {
+        return Err("--channels only works with MIDI files, without --arrange".into());
+    }
 
-    sink.append(buffer);
+    let (notes, voices) = match options.arrange {
+        Some(motors) => arrange_song(&options.song, motors)?,
+        None => load_song(&options.song, options.channels.as_ref())?,
+    };
 
-    sink.sleep_until_end();
+    let (mut notes, voices) = options.playback.apply(&notes, &voices)?;
 
-    Ok(())
-}
+    for note in &mut notes {
+        note.length_mcs = note.length_mcs * 100 / options.tempo_percent;
+    }
 
-#[cfg(all(not(feature="raspi"), not(feature = "rodio")))]
-fn play_data(_data: Vec<f32>) -> Result<(), Box<dyn Error>> {
-    println!("No way to play this. Try running with --features raspi or --features rodio.");
+    if let Some(mode) = options.fit {
+        let report: FitReport = fit_song(&mut notes, &machine.frequency_ranges(), mode);
 
-    Ok(())
-}
+        for &(motor_id, octaves) in &report.transposed {
+            eprintln!("Transposed motor `{}` by {:+} octaves to fit its range",
+                machine.motors[motor_id as usize].name, octaves);
+        }
 
-fn play_pachelbel() -> Result<(), Box<dyn Error>> {
-    #[cfg(feature = "raspi")]
-    let pins: Vec<GpioMotor> = vec![
-        gpio_motor(15)?,
-        gpio_motor(14)?,
-    ];
-
-    #[cfg(not(feature = "raspi"))]
-    let pins: Vec<SimpleAudioMotor> = vec![
-        SimpleAudioMotor::new(),
-        SimpleAudioMotor::new(),
-    ];
-
-    let voices: Vec<Voice> = vec![voice(0), voice(8)];
-
-    let notes: Vec<NoteInfo> = vec![
-        note( 1, 0, 14712, 120), // d
-        note( 2, 0, 11000, 120), // a
-        note( 3, 0, 12298, 120), // b
-        note( 4, 0,  9195, 120), // f# (thanks, Rob Paravonian)
-        note( 5, 0,  9839, 120), // g
-        note( 6, 0,  7356, 120), // d
-        note( 7, 0,  9839, 120), // g
-        note( 0, 0, 11000, 120), // a
-
-
-        note( 9, 1, 73561, 120), // f#
-        note(10, 1, 65795, 120), // e
-        note(11, 1, 58849, 120), // d
-        note(12, 1, 55000, 120), // c#
-        note(13, 1, 49193, 120), // b
-        note(14, 1, 44000, 120), // a
-        note(15, 1, 49193, 120), // b
-        note(16, 1, 55000, 120), // c#
-
-        note(17, 1, 58849, 120), // d
-        note(18, 1, 55000, 120), // c#
-        note(19, 1, 49193, 120), // b
-        note(20, 1, 44000, 120), // a
-        note(21, 1, 39355, 120), // g
-        note(22, 1, 36781, 120), // f#
-        note(23, 1, 39355, 120), // g
-        note(24, 1, 32898, 120), // e
-
-
-        note(25, 1, 29425,  60), // d
-        note(26, 1, 36781,  60), // f#
-        note(27, 1, 44000,  60), // a
-        note(28, 1, 39355,  60), // g
-        note(29, 1, 36781,  60), // f#
-        note(30, 1, 29425,  60), // d
-        note(31, 1, 36781,  60), // f
-        note(32, 1, 32898,  60), // e
-
-        note(33, 1, 29425,  60), // d
-        note(34, 1, 24597,  60), // b
-        note(35, 1, 29425,  60), // d
-        note(36, 1, 44000,  60), // a
-        note(37, 1, 39355,  60), // g
-        note(38, 1, 49193,  60), // b
-        note(39, 1, 44000,  60), // a
-        note(40, 1, 39355,  60), // g
-
-
-        note(41, 1, 36781,  60), // f#
-        note(42, 1, 29425,  60), // d
-        note(43, 1, 32898,  60), // e
-        note(44, 1, 55000,  60), // c#
-        note(45, 1, 58849,  60), // d
-        note(46, 1, 73561,  60), // f#
-        note(47, 1, 88000,  60), // a
-        note(48, 1, 44000,  60), // a
-
-        note(49, 1, 49193,  60), // b
-        note(50, 1, 39355,  60), // g
-        note(51, 1, 44000,  60), // a
-        note(52, 1, 36781,  60), // f#
-        note(53, 1, 29425,  60), // d
-        note(54, 1, 58849,  60), // d
-        // note(55, 1, 58849,  90), // d
-        NoteInfo {
-            next_note_index: 55,
-            motor_id: 1,
-            exit: false,
-            frequency_mchz: 588_490_000,
-            length_mcs: 900_000,
-            rearticulate: true,
-        },
-        note(56, 1, 55000,  30), // c#
-
-
-        note(57, 1, 58849, 120), // d
-
-        NoteInfo {
-            next_note_index: 0,
-            motor_id: 1,
-            exit: true,
-            frequency_mchz: 0,
-            length_mcs: 1_000_000,
-            rearticulate: false,
-        },
-    ];
-
-    #[cfg(feature = "raspi")]
-    let mut timer: NixTimer = NixTimer::new();
-
-    #[cfg(not(feature = "raspi"))]
-    let mut timer: SimpleAudioTimer = SimpleAudioTimer::new(44100, &pins);
-
-    println!("Playing...");
-    play_note_info_array(pins, notes, voices, &mut timer)?;
-
-    #[cfg(not(feature = "raspi"))]
-    play_data(timer.data)?;
+        if !report.moved.is_empty() {
+            eprintln!("Moved {} notes by octaves to fit the motors' ranges", report.moved.len());
+        }
 
-    Ok(())
+        if !report.unfit.is_empty() {
+            eprintln!("warning: {} notes don't fit their motors' ranges (the first is note {})",
+                report.unfit.len(), report.unfit[0]);
+        }
+    }
+
+    for problem in validate_song(&notes, &voices, None).iter().filter(|p| !p.is_fatal()) {
+        eprintln!("warning: {}", problem);
+    }
+
+    check_song(&notes, &voices, None)?;
+
+    Ok((notes, voices))
+}
+
+/// Let the user control the song by typing commands, until the song ends.
+fn control_from_stdin(control: PlaybackControl) {
+    println!("Type pause, resume, stop, seek <seconds> or status and press Enter to control the song.");
+
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let line: String = match line {
+                Ok(line) => line,
+                Err(_) => return,
+            };
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+
+            match words.as_slice() {
+                ["pause"] => control.pause(),
+                ["resume"] => control.resume(),
+                ["stop"] => control.stop(),
+                ["status"] => println!("{} at {:.1} seconds",
+                    if control.is_paused() { "Paused" } else { "Playing" },
+                    control.position_mcs() as f64 / 1_000_000.0),
+                ["seek", seconds] => match seconds.parse::<f64>() {
+                    Ok(seconds) if seconds >= 0.0 => control.seek((seconds * 1_000_000.0) as u64),
+                    _ => eprintln!("`{}` is not a number of seconds", seconds),
+                },
+                [] => { }
+                _ => eprintln!("unknown command `{}`", line.trim()),
+            }
+        }
+    });
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let builder: SongBuilder = hallelujah::build_song();
+fn play(options: &Options) -> Result<(), Box<dyn Error>> {
+    let backend: Box<dyn Backend> = match &options.backend {
+        Some(name) => find_backend(name)?,
+        None => default_backend(),
+    };
 
-    #[cfg(feature = "raspi")]
-    let pins: Vec<GpioMotor> = vec![
-        gpio_motor(15)?,
-        gpio_motor(14)?,
-    ];
+    let machine: Machine = load_machine(options)?;
+    let (notes, voices) = prepare_song(options, &machine)?;
 
-    #[cfg(not(feature = "raspi"))]
-    let pins: Vec<SimpleAudioMotor> = vec![
-        SimpleAudioMotor::new(),
-        SimpleAudioMotor::new(),
-    ];
+    let control: PlaybackControl = PlaybackControl::new();
+    shutdown::install_handlers(control.clone())?;
 
-    let voices: Vec<Voice> = builder.voices.into_iter().map(|v| voice(v.first_note_index)).collect();
+    if backend.supports_control() {
+        control_from_stdin(control.clone());
+    }
 
-    let notes: Vec<NoteInfo> = builder.notes;
+    backend.play(&machine, notes, voices, &control)?;
 
-    #[cfg(feature = "raspi")]
-    let mut timer: NixTimer = NixTimer::new();
+    if shutdown::was_interrupted() {
+        process::exit(130);
+    }
 
-    #[cfg(not(feature = "raspi"))]
-    let mut timer: SimpleAudioTimer = SimpleAudioTimer::new(44100, &pins);
+    Ok(())
+}
 
-    println!("Playing...");
-    play_note_info_array(pins, notes, voices, &mut timer)?;
+fn render(options: &Options, render_options: &RenderOptions) -> Result<(), Box<dyn Error>> {
+    let machine: Machine = load_machine(options)?;
+    let (notes, voices) = prepare_song(options, &machine)?;
 
-    #[cfg(not(feature = "raspi"))]
-    play_data(timer.data)?;
+    let data: Vec<f32> = render_audio(&machine, notes, voices)?;
+    write_wav_file(&render_options.out, &data, SAMPLE_RATE, render_options.format)?;
 
+    println!("Wrote {} seconds of audio to {}", data.len() as u32 / SAMPLE_RATE, render_options.out);
     Ok(())
 }
+
+fn run(command: Command) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::Play(options) => play(&options),
+        Command::Render(options, render_options) => render(&options, &render_options),
+        Command::List => {
+            for song in SONGS.iter() {
+                println!("{}", song);
+            }
+
+            Ok(())
+        }
+        Command::Backends => {
+            for backend in backends() {
+                println!("{:8} {}", backend.name(), backend.description());
+            }
+
+            Ok(())
+        }
+        Command::Help => {
+            print!("{}", USAGE);
+            Ok(())
+        }
+    }
+}
+
+fn main() {
+    let command: Command = match parse_args(env::args().skip(1)) {
+        Ok(command) => command,
+        Err(message) => {
+            eprintln!("ambrose: {}\n\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(error) = run(command) {
+        eprintln!("ambrose: {}", error);
+        process::exit(1);
+    }
+}
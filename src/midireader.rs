@@ -0,0 +1,545 @@
+use std::cmp::{
+    Reverse,
+    min,
+};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+
+use crate::arranger::PolyNote;
+
+use crate::notes::NoteInfo;
+
+use crate::pitch::{
+    EqualTemperament,
+    Pitch,
+};
+
+use crate::songbuilder::SongBuilder;
+
+// This reads Standard MIDI Files (format 0 or 1) and turns them into songs.
+//
+// By default, each track and channel that has any notes in it becomes its own
+// voice, in order of track and then channel, except that channel 10 (the
+// percussion channel) is left out. Alternatively, the caller can give a map
+// from (0-based) channel numbers to voices, in which case channels that aren't
+// in the map are left out, and several channels can share a voice.
+//
+// Each voice can only play one note at a time, so when notes overlap within a
+// voice, each new note cuts off the one before it, and when several notes in a
+// voice start at once, the highest one wins. Gaps between notes become rests.
+// Every voice is padded with a rest so that they all end at the same time, and
+// then ends with an exit note.
+//
+// `read_midi_notes` reads the notes without splitting them into voices, so
+// that they can be arranged for the motors by an `Arranger` instead.
+
+const PERCUSSION_CHANNEL: u8 = 9;
+const DEFAULT_TEMPO_MCS: u64 = 500_000;
+
+#[derive(Debug)]
+pub struct MidiReadError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for MidiReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "byte {}: {}", self.offset, self.message)
+    }
+}
+
+impl Error for MidiReadError { }
+
+fn error(offset: usize, message: &str) -> MidiReadError {
+    MidiReadError { offset, message: message.to_string() }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn at_end(&self) -> bool {
+        self.offset >= self.data.len()
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], MidiReadError> {
+        if self.data.len() - self.offset < count {
+            return Err(error(self.data.len(), "unexpected end of file"));
+        }
+
+        let result: &'a [u8] = &self.data[self.offset..self.offset + count];
+        self.offset += count;
+        Ok(result)
+    }
+
+    fn u8(&mut self) -> Result<u8, MidiReadError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, MidiReadError> {
+        let bytes: &[u8] = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, MidiReadError> {
+        let bytes: &[u8] = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Read a variable-length quantity.
+    fn vlq(&mut self) -> Result<u32, MidiReadError> {
+        let start: usize = self.offset;
+        let mut value: u32 = 0;
+
+        for _ in 0..4 {
+            let byte: u8 = self.u8()?;
+            value = (value << 7) | (byte & 0x7f) as u32;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(error(start, "variable-length quantity is too long"))
+    }
+}
+
+struct MidiNote {
+    track: usize,
+    channel: u8,
+    key: u8,
+    start_tick: u64,
+    end_tick: u64,
+}
+
+/// Everything we need out of a MIDI file.
+struct MidiData {
+    notes: Vec<MidiNote>,
+    /// Pairs of (tick, microseconds per quarter note), in order of tick.
+    tempos: Vec<(u64, u64)>,
+    end_tick: u64,
+}
+
+fn read_track(
+    reader: &mut Reader,
+    track: usize,
+    end_offset: usize,
+    data: &mut MidiData,
+) -> Result<(), MidiReadError> {
+    let mut tick: u64 = 0;
+    let mut running_status: Option<u8> = None;
+    let mut open_notes: HashMap<(u8, u8), Vec<u64>> = HashMap::new();
+
+    while reader.offset < end_offset {
+        tick += reader.vlq()? as u64;
+
+        let status_offset: usize = reader.offset;
+        let mut status: u8 = reader.u8()?;
+
+        if status < 0x80 {
+            status = running_status.ok_or_else(||
+                error(status_offset, "data byte without a status byte"))?;
+            reader.offset -= 1;
+        }
+
+        match status {
+            0xff => {
+                let meta_type: u8 = reader.u8()?;
+                let length: usize = reader.vlq()? as usize;
+                let meta_data: &[u8] = reader.bytes(length)?;
+
+                match meta_type {
+                    0x2f => break,
+                    0x51 if length == 3 => {
+                        let tempo: u64 =
+                            ((meta_data[0] as u64) << 16) | ((meta_data[1] as u64) << 8) | meta_data[2] as u64;
+                        data.tempos.push((tick, tempo));
+                    }
+                    _ => { }
+                }
+            }
+            0xf0 | 0xf7 => {
+                running_status = None;
+                let length: usize = reader.vlq()? as usize;
+                reader.bytes(length)?;
+            }
+            0x80..=0xef => {
+                running_status = Some(status);
+                let channel: u8 = status & 0x0f;
+
+                match status & 0xf0 {
+                    0xc0 | 0xd0 => { reader.u8()?; }
+                    kind => {
+                        let key: u8 = reader.u8()?;
+                        let velocity: u8 = reader.u8()?;
+
+                        if kind == 0x90 && velocity > 0 {
+                            open_notes.entry((channel, key)).or_default().push(tick);
+                        } else if kind == 0x80 || kind == 0x90 {
+                            let starts: &mut Vec<u64> = open_notes.entry((channel, key)).or_default();
+
+                            if !starts.is_empty() {
+                                let start_tick: u64 = starts.remove(0);
+                                data.notes.push(MidiNote { track, channel, key, start_tick, end_tick: tick });
+                            }
+                        }
+                    }
+                }
+            }
+            _ => return Err(error(status_offset, "unknown status byte")),
+        }
+    }
+
+    for ((channel, key), starts) in open_notes {
+        for start_tick in starts {
+            data.notes.push(MidiNote { track, channel, key, start_tick, end_tick: tick });
+        }
+    }
+
+    data.end_tick = data.end_tick.max(tick);
+    reader.offset = end_offset;
+
+    Ok(())
+}
+
+/// Convert ticks into microseconds using the tempo map.
+fn tick_to_mcs(tick: u64, tempos: &[(u64, u64)], ticks_per_quarter: u64) -> u64 {
+    let mut mcs_times_ticks: u128 = 0;
+    let mut segment_start: u64 = 0;
+    let mut tempo: u64 = DEFAULT_TEMPO_MCS;
+
+    for &(tempo_tick, new_tempo) in tempos {
+        if tempo_tick >= tick {
+            break;
+        }
+
+        mcs_times_ticks += (tempo_tick - segment_start) as u128 * tempo as u128;
+        segment_start = tempo_tick;
+        tempo = new_tempo;
+    }
+
+    mcs_times_ticks += (tick - segment_start) as u128 * tempo as u128;
+    (mcs_times_ticks / ticks_per_quarter as u128) as u64
+}
+
+fn midi_note(frequency_mchz: u64, length_mcs: u64) -> NoteInfo {
+    NoteInfo {
+        next_note_index: 0,
+        motor_id: 0,
+        exit: false,
+        stop: false,
+        frequency_mchz,
+        length_mcs,
+        rearticulate: true,
+        repeat: None,
+    }
+}
+
+/// Read the notes and tempo changes from a MIDI file, returning them along with
+/// the number of ticks per quarter note.
+fn read_midi_data(bytes: &[u8]) -> Result<(MidiData, u64), MidiReadError> {
+    let mut reader: Reader = Reader { data: bytes, offset: 0 };
+
+    if reader.bytes(4)? != b"MThd" {
+        return Err(error(0, "not a MIDI file"));
+    }
+
+    let header_length: usize = reader.u32()? as usize;
+    let header_end: usize = reader.offset.checked_add(header_length)
+        .ok_or_else(|| error(4, "the header is too long"))?;
+    let format: u16 = reader.u16()?;
+    let track_count: u16 = reader.u16()?;
+    let division_offset: usize = reader.offset;
+    let division: u16 = reader.u16()?;
+    reader.offset = header_end;
+
+    if format > 1 {
+        return Err(error(8, "only MIDI formats 0 and 1 are supported"));
+    }
+
+    // With SMPTE timing, each tick is a fixed fraction of a second, which is
+    // the same as having a fixed tempo of one second per (frames per second
+    // times ticks per frame) ticks.
+    let (ticks_per_quarter, fixed_tempo): (u64, Option<u64>) = if division & 0x8000 != 0 {
+        let frames_per_second: u64 = (-(((division >> 8) as u8 as i8) as i64)) as u64;
+        let ticks_per_frame: u64 = (division & 0xff) as u64;
+        (frames_per_second * ticks_per_frame, Some(1_000_000))
+    } else {
+        (division as u64, None)
+    };
+
+    if ticks_per_quarter == 0 {
+        return Err(error(division_offset, "invalid time division"));
+    }
+
+    let mut data: MidiData = MidiData { notes: vec![], tempos: vec![], end_tick: 0 };
+    let mut track: usize = 0;
+
+    while track < track_count as usize && !reader.at_end() {
+        let chunk_type: &[u8] = reader.bytes(4)?;
+        let length: usize = reader.u32()? as usize;
+        let end_offset: usize = match reader.offset.checked_add(length) {
+            Some(end_offset) if end_offset <= bytes.len() => end_offset,
+            _ => return Err(error(reader.offset - 4, "chunk runs past the end of the file")),
+        };
+
+        if chunk_type == b"MTrk" {
+            read_track(&mut reader, track, end_offset, &mut data)?;
+            track += 1;
+        } else {
+            reader.offset = end_offset;
+        }
+    }
+
+    match fixed_tempo {
+        Some(tempo) => data.tempos = vec![(0, tempo)],
+        None => data.tempos.sort_by_key(|&(tick, _)| tick),
+    }
+
+    if data.notes.is_empty() {
+        return Err(error(0, "the file doesn't contain any notes"));
+    }
+
+    Ok((data, ticks_per_quarter))
+}
+
+/// Read a song from the contents of a MIDI file. If `channel_voices` is given,
+/// it maps channels to voices; otherwise, each track and channel gets its own
+/// voice.
+pub fn read_midi(
+    bytes: &[u8],
+    channel_voices: Option<&HashMap<u8, u8>>,
+) -> Result<SongBuilder, MidiReadError> {
+    let (data, ticks_per_quarter) = read_midi_data(bytes)?;
+
+    // Work out which voice each note belongs to.
+    let voice_of: HashMap<(usize, u8), u8> = match channel_voices {
+        Some(map) => data.notes.iter()
+            .filter_map(|n| map.get(&n.channel).map(|&v| ((n.track, n.channel), v)))
+            .collect(),
+        None => {
+            let mut sources: Vec<(usize, u8)> = data.notes.iter()
+                .filter(|n| n.channel != PERCUSSION_CHANNEL)
+                .map(|n| (n.track, n.channel))
+                .collect();
+            sources.sort_unstable();
+            sources.dedup();
+
+            if sources.len() > u8::MAX as usize + 1 {
+                return Err(error(0, "the file has too many tracks and channels to give each its own voice"));
+            }
+
+            sources.into_iter().enumerate().map(|(v, source)| (source, v as u8)).collect()
+        }
+    };
+
+    let voice_count: usize = match voice_of.values().max() {
+        Some(&max_voice) => max_voice as usize + 1,
+        None => return Err(error(0, "none of the file's channels are mapped to a voice")),
+    };
+
+    let mut voice_notes: Vec<Vec<(u64, u64, u8)>> = (0..voice_count).map(|_| vec![]).collect();
+
+    for note in &data.notes {
+        if let Some(&voice) = voice_of.get(&(note.track, note.channel)) {
+            voice_notes[voice as usize].push((note.start_tick, note.end_tick, note.key));
+        }
+    }
+
+    let to_mcs = |tick: u64| tick_to_mcs(tick, &data.tempos, ticks_per_quarter);
+    let song_end_mcs: u64 = to_mcs(data.end_tick);
+
+    let mut builder: SongBuilder = SongBuilder::new();
+    let tuning: EqualTemperament = EqualTemperament::default();
+
+    for (voice, notes) in voice_notes.iter_mut().enumerate() {
+        // Of the notes that start at once, only the highest one is kept.
+        notes.sort_unstable_by_key(|&(start_tick, _, key)| (start_tick, Reverse(key)));
+        notes.dedup_by_key(|&mut (start_tick, _, _)| start_tick);
+
+        let mut current_mcs: u64 = 0;
+
+        for i in 0..notes.len() {
+            let (start_tick, mut end_tick, key) = notes[i];
+
+            if let Some(&(next_start_tick, _, _)) = notes.get(i + 1) {
+                end_tick = min(end_tick, next_start_tick);
+            }
+
+            let start_mcs: u64 = to_mcs(start_tick);
+            let end_mcs: u64 = to_mcs(end_tick);
+
+            if end_mcs <= start_mcs {
+                continue;
+            }
+
+            if start_mcs > current_mcs {
+                builder.add(voice as u8, midi_note(0, start_mcs - current_mcs).rest());
+            }
+
+            builder.add(voice as u8, midi_note(Pitch::midi(key as i32).frequency_mchz(&tuning), end_mcs - start_mcs));
+            current_mcs = end_mcs;
+        }
+
+        if song_end_mcs > current_mcs {
+            builder.add(voice as u8, midi_note(0, song_end_mcs - current_mcs).rest());
+        }
+
+        builder.add(voice as u8, midi_note(0, 0).exit());
+    }
+
+    Ok(builder)
+}
+
+/// Read a song from a MIDI file.
+pub fn read_midi_file(
+    path: &str,
+    channel_voices: Option<&HashMap<u8, u8>>,
+) -> Result<SongBuilder, Box<dyn Error>> {
+    let bytes: Vec<u8> = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    Ok(read_midi(&bytes, channel_voices).map_err(|e| format!("{}: {}", path, e))?)
+}
+
+/// Read all of the notes from the contents of a MIDI file, except for the ones
+/// on the percussion channel, without splitting them into voices. They can be
+/// split up with an `Arranger` instead.
+pub fn read_midi_notes(bytes: &[u8]) -> Result<Vec<PolyNote>, MidiReadError> {
+    let (data, ticks_per_quarter) = read_midi_data(bytes)?;
+    let tuning: EqualTemperament = EqualTemperament::default();
+
+    let to_mcs = |tick: u64| tick_to_mcs(tick, &data.tempos, ticks_per_quarter);
+
+    Ok(data.notes.iter()
+        .filter(|n| n.channel != PERCUSSION_CHANNEL)
+        .map(|n| PolyNote {
+            start_mcs: to_mcs(n.start_tick),
+            length_mcs: to_mcs(n.end_tick) - to_mcs(n.start_tick),
+            frequency_mchz: Pitch::midi(n.key as i32).frequency_mchz(&tuning),
+        })
+        .collect())
+}
+
+/// Read all of the notes from a MIDI file, as `read_midi_notes` does.
+pub fn read_midi_notes_file(path: &str) -> Result<Vec<PolyNote>, Box<dyn Error>> {
+    let bytes: Vec<u8> = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    Ok(read_midi_notes(&bytes).map_err(|e| format!("{}: {}", path, e))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::midireader::*;
+
+    fn midi_file(tracks: &[&[u8]]) -> Vec<u8> {
+        let mut bytes: Vec<u8> = b"MThd\0\0\0\x06\0\x01".to_vec();
+        bytes.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&96u16.to_be_bytes());
+
+        for track in tracks {
+            bytes.extend_from_slice(b"MTrk");
+            bytes.extend_from_slice(&(track.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(track);
+        }
+
+        bytes
+    }
+
+    #[test]
+    fn can_read_midi() {
+        let bytes: Vec<u8> = midi_file(&[
+            // Tempo of 250,000 microseconds per quarter note.
+            &[0x00, 0xff, 0x51, 0x03, 0x03, 0xd0, 0x90, 0x00, 0xff, 0x2f, 0x00],
+            // A4 for a quarter note, then a quarter rest, then (with running
+            // status and a zero-velocity note off) A5 for a quarter note.
+            &[
+                0x00, 0x90, 69, 100,
+                0x60, 0x80, 69, 0,
+                0x60, 0x90, 81, 100,
+                0x60, 81, 0,
+                0x00, 0xff, 0x2f, 0x00,
+            ],
+            // C4 on channel 2 for two quarter notes, running into the end.
+            &[0x00, 0x91, 60, 100, 0x81, 0x40, 0xff, 0x2f, 0x00],
+        ]);
+
+        let builder: SongBuilder = read_midi(&bytes, None).unwrap();
+        assert_eq!(builder.voices.len(), 2);
+
+        let notes: &Vec<NoteInfo> = &builder.notes;
+        let lengths: Vec<(u64, u64)> = notes.iter().map(|n| (n.frequency_mchz, n.length_mcs)).collect();
+        assert_eq!(lengths, vec![
+            (440_000_000, 250_000), (0, 250_000), (880_000_000, 250_000), (0, 0),
+            (261_625_565, 500_000), (0, 250_000), (0, 0),
+        ]);
+        assert!(notes[3].exit);
+        assert!(notes[6].exit);
+        assert_eq!(notes[4].motor_id, 1);
+    }
+
+    #[test]
+    fn can_map_channels_to_voices() {
+        let bytes: Vec<u8> = midi_file(&[&[
+            0x00, 0x90, 69, 100,
+            0x00, 0x91, 72, 100,
+            0x60, 0x80, 69, 0,
+            0x00, 0x81, 72, 0,
+            0x00, 0xff, 0x2f, 0x00,
+        ]]);
+
+        let mut map: HashMap<u8, u8> = HashMap::new();
+        map.insert(0, 0);
+        map.insert(1, 0);
+
+        let builder: SongBuilder = read_midi(&bytes, Some(&map)).unwrap();
+        assert_eq!(builder.voices.len(), 1);
+        assert_eq!(builder.notes[0].frequency_mchz, 523_251_131);
+        assert_eq!(builder.notes[0].length_mcs, 500_000);
+    }
+
+    #[test]
+    fn highest_of_simultaneous_notes_wins() {
+        // A4 for two quarter notes, with C5 on top of it for one.
+        let bytes: Vec<u8> = midi_file(&[&[
+            0x00, 0x90, 69, 100,
+            0x00, 0x90, 72, 100,
+            0x60, 0x80, 72, 0,
+            0x60, 0x80, 69, 0,
+            0x00, 0xff, 0x2f, 0x00,
+        ]]);
+
+        let builder: SongBuilder = read_midi(&bytes, None).unwrap();
+        assert_eq!(builder.notes[0].frequency_mchz, 523_251_131);
+        assert_eq!(builder.notes[0].length_mcs, 500_000);
+        assert_eq!(builder.notes[1].frequency_mchz, 0);
+    }
+
+    #[test]
+    fn can_read_all_notes() {
+        let bytes: Vec<u8> = midi_file(&[&[
+            0x00, 0x90, 69, 100,
+            0x00, 0x90, 72, 100,
+            0x00, 0x99, 36, 100,
+            0x60, 0x80, 69, 0,
+            0x00, 0x80, 72, 0,
+            0x00, 0x89, 36, 0,
+            0x00, 0xff, 0x2f, 0x00,
+        ]]);
+
+        let mut notes: Vec<PolyNote> = read_midi_notes(&bytes).unwrap();
+        notes.sort_by_key(|n| n.frequency_mchz);
+
+        assert_eq!(notes, vec![
+            PolyNote { start_mcs: 0, length_mcs: 500_000, frequency_mchz: 440_000_000 },
+            PolyNote { start_mcs: 0, length_mcs: 500_000, frequency_mchz: 523_251_131 },
+        ]);
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        assert!(read_midi(b"RIFF", None).is_err());
+        assert!(read_midi(&midi_file(&[&[0x00, 0x90, 69]]), None).is_err());
+    }
+}
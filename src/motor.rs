@@ -1,10 +1,18 @@
-#[cfg(not(feature = "raspi"))]
 use std::{
     cell::RefCell,
     rc::Rc,
 };
 #[cfg(feature = "raspi")]
-use std::error::Error;
+use std::{
+    error::Error,
+    sync::Arc,
+    sync::Mutex,
+    sync::MutexGuard,
+    thread::sleep,
+    time::Duration,
+};
+
+use serde::Deserialize;
 
 #[cfg(feature = "raspi")]
 use rppal::{
@@ -12,36 +20,840 @@ use rppal::{
     gpio::OutputPin,
 };
 
+/// The shape of the pulse on a motor's pin for each step.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pulse {
+    /// The pin is high for the given percentage of each cycle, whatever the
+    /// frequency.
+    Duty { percent: f64 },
+    /// The pin is high for a fixed number of microseconds each step, which is
+    /// all that many STEP/DIR drivers need. At frequencies where that would be
+    /// more than half of the cycle, the pin is high for half of the cycle.
+    Width { mcs: u64 },
+}
+
+impl Default for Pulse {
+    /// A square wave.
+    fn default() -> Self {
+        Pulse::Duty { percent: 50.0 }
+    }
+}
+
+/// A stepper driver whose microstep mode can be set with three mode pins.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MicrostepDriver {
+    /// An A4988, with MS1, MS2 and MS3 pins, for up to 16 microsteps.
+    A4988,
+    /// A DRV8825, with MODE0, MODE1 and MODE2 pins, for up to 32 microsteps.
+    Drv8825,
+}
+
+impl MicrostepDriver {
+    /// The levels of the driver's three mode pins, in order, for the given
+    /// number of microsteps per step, if the driver has that mode.
+    pub fn mode_pin_levels(&self, microsteps: u32) -> Option<[bool; 3]> {
+        match (self, microsteps) {
+            (_, 1) => Some([false, false, false]),
+            (_, 2) => Some([true, false, false]),
+            (_, 4) => Some([false, true, false]),
+            (_, 8) => Some([true, true, false]),
+            (MicrostepDriver::A4988, 16) => Some([true, true, true]),
+            (MicrostepDriver::Drv8825, 16) => Some([false, false, true]),
+            (MicrostepDriver::Drv8825, 32) => Some([true, false, true]),
+            _ => None,
+        }
+    }
+}
+
+/// The order in which a motor's four coils are energized, for motors that are
+/// driven coil by coil instead of through a step driver.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CoilSequence {
+    /// One coil at a time, which uses the least current.
+    Wave,
+    /// Two coils at a time, which gives the most torque.
+    Full,
+    /// One and then two coils at a time, taking half steps.
+    Half,
+}
+
+impl CoilSequence {
+    /// Which of the coils are energized in each phase of the sequence.
+    pub fn phases(&self) -> &'static [[bool; 4]] {
+        match self {
+            CoilSequence::Wave => &[
+                [true, false, false, false],
+                [false, true, false, false],
+                [false, false, true, false],
+                [false, false, false, true],
+            ],
+            CoilSequence::Full => &[
+                [true, true, false, false],
+                [false, true, true, false],
+                [false, false, true, true],
+                [true, false, false, true],
+            ],
+            CoilSequence::Half => &[
+                [true, false, false, false],
+                [true, true, false, false],
+                [false, true, false, false],
+                [false, true, true, false],
+                [false, false, true, false],
+                [false, false, true, true],
+                [false, false, false, true],
+                [true, false, false, true],
+            ],
+        }
+    }
+
+    /// How many phases of the sequence it takes to make a full step.
+    pub fn microsteps(&self) -> u32 {
+        match self {
+            CoilSequence::Half => 2,
+            CoilSequence::Wave | CoilSequence::Full => 1,
+        }
+    }
+}
+
 pub trait Motor {
     /// Command the motor to advance one step. 
     fn advance(&mut self);
 
     /// Prepare the motor to advance another step later.
     fn reset(&mut self);
+
+    /// The shape of the pulse to drive the motor with for each step.
+    fn pulse(&self) -> Pulse { Pulse::default() }
+
+    /// Power the motor's driver up or down, for motors whose driver has an
+    /// enable pin. Motors start out disabled.
+    fn set_enabled(&mut self, _enabled: bool) { }
+
+    /// How long the motor can go without playing a note before it's
+    /// disabled, or `None` to keep it enabled until the song ends.
+    fn idle_timeout_mcs(&self) -> Option<u64> { None }
+
+    /// How many steps the motor takes for each full step, in its current
+    /// microstep mode.
+    fn microsteps(&self) -> u32 { 1 }
+
+    /// Switch the motor to the given microstep mode. Motors that can't switch
+    /// to that mode stay in the mode they're in.
+    fn set_microsteps(&mut self, _microsteps: u32) { }
+
+    /// Put the motor back where it started, for motors that have to keep
+    /// track of where they are. This can take a while, so it's done when a
+    /// song ends or is stopped, but not when the motor is dropped.
+    fn park(&mut self) { }
+}
+
+/// A machine can have motors of different kinds, so they're often boxed.
+impl<M: Motor + ?Sized> Motor for Box<M> {
+    fn advance(&mut self) {
+        (**self).advance();
+    }
+
+    fn reset(&mut self) {
+        (**self).reset();
+    }
+
+    fn pulse(&self) -> Pulse {
+        (**self).pulse()
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        (**self).set_enabled(enabled);
+    }
+
+    fn idle_timeout_mcs(&self) -> Option<u64> {
+        (**self).idle_timeout_mcs()
+    }
+
+    fn microsteps(&self) -> u32 {
+        (**self).microsteps()
+    }
+
+    fn set_microsteps(&mut self, microsteps: u32) {
+        (**self).set_microsteps(microsteps);
+    }
+
+    fn park(&mut self) {
+        (**self).park();
+    }
+}
+
+/// The motors can be lent to a song, so that they can be parked afterwards.
+impl<M: Motor + ?Sized> Motor for &mut M {
+    fn advance(&mut self) {
+        (**self).advance();
+    }
+
+    fn reset(&mut self) {
+        (**self).reset();
+    }
+
+    fn pulse(&self) -> Pulse {
+        (**self).pulse()
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        (**self).set_enabled(enabled);
+    }
+
+    fn idle_timeout_mcs(&self) -> Option<u64> {
+        (**self).idle_timeout_mcs()
+    }
+
+    fn microsteps(&self) -> u32 {
+        (**self).microsteps()
+    }
+
+    fn set_microsteps(&mut self, microsteps: u32) {
+        (**self).set_microsteps(microsteps);
+    }
+
+    fn park(&mut self) {
+        (**self).park();
+    }
+}
+
+/// The finest microstep mode that any motor can be in.
+pub const MAX_MICROSTEPS: u32 = 256;
+
+/// Keeps track of where a motor's carriage is along a lead screw or belt, so
+/// that the motor can turn around before the carriage reaches either end.
+///
+/// Positions and lengths can be counted in any unit. A motor that can switch
+/// between microstep modes counts in its finest microsteps, and takes steps of
+/// different sizes depending on its mode.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Travel {
+    /// The ends of the travel.
+    min: i64,
+    max: i64,
+    position: i64,
+    forward: bool,
+}
+
+impl Travel {
+    /// Travel of the given number of steps from end to end, for a carriage
+    /// that starts in the middle. Positions are counted from the middle.
+    pub fn new(length_steps: u64) -> Self {
+        let half: i64 = (length_steps / 2) as i64;
+        Travel { min: -half, max: half, position: 0, forward: true }
+    }
+
+    /// Travel of the given number of steps from end to end, for a carriage
+    /// that starts at the back end, such as a floppy drive's head after it's
+    /// been homed. Positions are counted from the back end.
+    pub fn from_start(length_steps: u64) -> Self {
+        Travel { min: 0, max: length_steps as i64, position: 0, forward: true }
+    }
+
+    pub fn position(&self) -> i64 {
+        self.position
+    }
+
+    pub fn is_forward(&self) -> bool {
+        self.forward
+    }
+
+    /// Turn towards where the carriage started, to take it back there.
+    /// Returns how many steps away it is.
+    pub fn turn_back(&mut self) -> u64 {
+        if self.position != 0 {
+            self.forward = self.position < 0;
+        }
+
+        self.position.unsigned_abs()
+    }
+
+    /// Count a step of 1 in the current direction. Returns true if the
+    /// carriage has reached the end of its travel, so the motor has to turn
+    /// around before its next step.
+    pub fn step(&mut self) -> bool {
+        self.step_by(1)
+    }
+
+    /// Count a step of the given size in the current direction. Returns true
+    /// if another step of the same size would take the carriage past the end
+    /// of its travel, so the motor has to turn around before its next step.
+    pub fn step_by(&mut self, size: u64) -> bool {
+        self.position += if self.forward { size as i64 } else { -(size as i64) };
+        self.prepare_step(size)
+    }
+
+    /// Turn around if a step of the given size would take the carriage past
+    /// the end of its travel. Returns true if it turned around.
+    pub fn prepare_step(&mut self, size: u64) -> bool {
+        let blocked: bool = if self.forward {
+            self.position + size as i64 > self.max
+        } else {
+            self.position - (size as i64) < self.min
+        };
+
+        if blocked {
+            self.forward = !self.forward;
+        }
+
+        blocked
+    }
 }
 
+#[cfg(feature = "raspi")]
+use crate::shutdown::{
+    ResetGuard,
+    on_emergency,
+};
+
+/// An output pin that's shared with the pin's reset action.
+#[cfg(feature = "raspi")]
+type SharedPin = Arc<Mutex<OutputPin>>;
+
 #[cfg(feature = "raspi")]
 pub struct GpioMotor {
-    output_pin: OutputPin,
+    output_pin: SharedPin,
+    pulse: Pulse,
+    _reset_guard: ResetGuard,
 }
 
 #[cfg(feature = "raspi")]
-pub fn gpio_motor(pin_number: u8) -> Result<GpioMotor, Box<dyn Error>> {
-    let output_pin: OutputPin = Gpio::new()?.get(pin_number)?.into_output();
-    Ok(GpioMotor { output_pin })
+fn lock_pin(pin: &Mutex<OutputPin>) -> MutexGuard<'_, OutputPin> {
+    pin.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(feature = "raspi")]
+fn set_pin(pin: &Mutex<OutputPin>, high: bool) {
+    if high {
+        lock_pin(pin).set_high();
+    } else {
+        lock_pin(pin).set_low();
+    }
+}
+
+/// Set up a GPIO pin as an output, starting at its safe level, which is low
+/// unless `safe_high` is set. The pin is put back to its safe level if ambrose
+/// is interrupted or panics, for as long as the guard is alive, and it stays
+/// as it is after ambrose exits.
+#[cfg(feature = "raspi")]
+fn output_pin(pin_number: u8, safe_high: bool) -> Result<(SharedPin, ResetGuard), Box<dyn Error>> {
+    let mut output_pin: OutputPin = Gpio::new()?.get(pin_number)?.into_output();
+    output_pin.set_reset_on_drop(false);
+
+    let output_pin: SharedPin = Arc::new(Mutex::new(output_pin));
+    set_pin(&output_pin, safe_high);
+
+    let emergency_pin: SharedPin = output_pin.clone();
+    let reset_guard: ResetGuard = on_emergency(move || set_pin(&emergency_pin, safe_high));
+
+    Ok((output_pin, reset_guard))
+}
+
+/// Set up a motor on the given GPIO pin, stepped with the given pulse. The pin
+/// is driven low when the motor is dropped, and if ambrose is interrupted or
+/// panics, and it stays low after ambrose exits.
+#[cfg(feature = "raspi")]
+pub fn gpio_motor(pin_number: u8, pulse: Pulse) -> Result<GpioMotor, Box<dyn Error>> {
+    let (output_pin, _reset_guard) = output_pin(pin_number, false)?;
+
+    Ok(GpioMotor { output_pin, pulse, _reset_guard })
 }
 
 #[cfg(feature = "raspi")]
 impl Motor for GpioMotor {
     fn advance(&mut self) {
-        self.output_pin.set_high();
+        lock_pin(&self.output_pin).set_high();
     }
 
     fn reset(&mut self) {
-        self.output_pin.set_low();
+        lock_pin(&self.output_pin).set_low();
+    }
+
+    fn pulse(&self) -> Pulse {
+        self.pulse
+    }
+}
+
+#[cfg(feature = "raspi")]
+impl Drop for GpioMotor {
+    fn drop(&mut self) {
+        self.reset();
     }
 }
 
+/// A motor whose driver (such as an A4988 or a DRV8825) has a STEP pin and a
+/// DIR pin. The motor turns around whenever it reaches the end of its travel,
+/// so that it never runs its carriage into the end.
+///
+/// The STEP pin is driven by another motor, which may be able to switch
+/// between microstep modes, so the carriage's travel is counted in the finest
+/// microsteps, and each step moves it further in coarser modes.
+#[cfg(feature = "raspi")]
+pub struct StepDirMotor<M: Motor = GpioMotor> {
+    step: M,
+    dir_pin: SharedPin,
+    /// The level of the DIR pin for moving forwards.
+    forward_high: bool,
+    travel: Travel,
+    _dir_reset_guard: ResetGuard,
+}
+
+/// Set up a STEP/DIR motor, stepped by the given motor, with its DIR pin on
+/// the given GPIO pin and the given length of travel in full steps. DIR is
+/// high for forwards. It's driven low when the motor is dropped, and if ambrose
+/// is interrupted or panics.
+#[cfg(feature = "raspi")]
+pub fn step_dir_motor<M: Motor>(step: M, dir_pin: u8, travel_steps: u64) -> Result<StepDirMotor<M>, Box<dyn Error>> {
+    let (dir_pin, _dir_reset_guard) = output_pin(dir_pin, false)?;
+
+    let motor: StepDirMotor<M> = StepDirMotor {
+        step,
+        dir_pin,
+        forward_high: true,
+        travel: Travel::new(travel_steps * MAX_MICROSTEPS as u64),
+        _dir_reset_guard,
+    };
+
+    motor.set_direction();
+    Ok(motor)
+}
+
+/// How long a STEP/DIR motor takes for each step when it's taking its
+/// carriage back to where it started, which is slow enough for any motor.
+#[cfg(feature = "raspi")]
+const STEP_DIR_PARKING_STEP_TIME: Duration = Duration::from_millis(2);
+
+#[cfg(feature = "raspi")]
+impl<M: Motor> StepDirMotor<M> {
+    /// Set the DIR pin for the direction that the carriage is travelling in.
+    fn set_direction(&self) {
+        set_pin(&self.dir_pin, self.travel.is_forward() == self.forward_high);
+    }
+
+    /// How far each step moves the carriage, in the finest microsteps.
+    fn step_size(&self) -> u64 {
+        (MAX_MICROSTEPS / self.step.microsteps()) as u64
+    }
+
+    /// Take one step, slowly enough for the carriage to keep up, without
+    /// counting it.
+    fn step_slowly(&mut self, step_time: Duration) {
+        sleep(step_time / 2);
+        self.step.advance();
+        sleep(step_time / 2);
+        self.step.reset();
+    }
+
+    /// Take the carriage back to where it started (to within a step, if it
+    /// has been in a finer mode than it's in now), taking the given time for
+    /// each full step.
+    fn go_back(&mut self, step_time: Duration) {
+        self.step.reset();
+
+        let size: u64 = self.step_size();
+        let steps: u64 = self.travel.turn_back() / size;
+        self.set_direction();
+
+        for _ in 0..steps {
+            self.step_slowly(step_time / self.step.microsteps());
+            self.travel.step_by(size);
+        }
+    }
+}
+
+#[cfg(feature = "raspi")]
+impl<M: Motor> Motor for StepDirMotor<M> {
+    fn advance(&mut self) {
+        self.step.advance();
+
+        // The next step is at least half a cycle away, which gives the driver
+        // plenty of time to see the new direction.
+        if self.travel.step_by(self.step_size()) {
+            self.set_direction();
+        }
+    }
+
+    fn reset(&mut self) {
+        self.step.reset();
+    }
+
+    fn pulse(&self) -> Pulse {
+        self.step.pulse()
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.step.set_enabled(enabled);
+    }
+
+    fn idle_timeout_mcs(&self) -> Option<u64> {
+        self.step.idle_timeout_mcs()
+    }
+
+    fn microsteps(&self) -> u32 {
+        self.step.microsteps()
+    }
+
+    // Modes are only switched between steps, so if a step in the new mode
+    // would go past the end, there's time to turn around first.
+    fn set_microsteps(&mut self, microsteps: u32) {
+        self.step.set_microsteps(microsteps);
+
+        if self.travel.prepare_step(self.step_size()) {
+            self.set_direction();
+        }
+    }
+
+    fn park(&mut self) {
+        self.go_back(STEP_DIR_PARKING_STEP_TIME);
+    }
+}
+
+#[cfg(feature = "raspi")]
+impl<M: Motor> Drop for StepDirMotor<M> {
+    fn drop(&mut self) {
+        lock_pin(&self.dir_pin).set_low();
+    }
+}
+
+/// How long a floppy drive's head takes to move one track.
+#[cfg(feature = "raspi")]
+const FLOPPY_SEEK_TIME: Duration = Duration::from_millis(3);
+
+/// A floppy drive, played by moving its head back and forth between tracks with
+/// the drive's STEP and DIR lines. The drive can be selected with its own
+/// drive-select line, or it can be jumpered to be always selected.
+///
+/// Like every line on the floppy interface, drive select is active low, so
+/// it's driven high when the drive is let go of. DIR is low for moving
+/// forwards, towards the middle of the disk.
+#[cfg(feature = "raspi")]
+pub struct FloppyMotor {
+    head: StepDirMotor,
+    select_pin: Option<(SharedPin, ResetGuard)>,
+}
+
+/// Set up a floppy drive on the given GPIO pins, with the given number of
+/// tracks. The drive is selected, and its head is moved back to track 0 by
+/// stepping it backwards at least as many times as there are tracks, so this
+/// takes a moment.
+#[cfg(feature = "raspi")]
+pub fn floppy_motor(
+    step_pin: u8,
+    dir_pin: u8,
+    select_pin: Option<u8>,
+    tracks: u64,
+    pulse: Pulse,
+) -> Result<FloppyMotor, Box<dyn Error>> {
+    let select_pin: Option<(SharedPin, ResetGuard)> = match select_pin {
+        Some(pin_number) => {
+            let (pin, guard) = output_pin(pin_number, true)?;
+            lock_pin(&pin).set_low();
+            Some((pin, guard))
+        }
+        None => None,
+    };
+
+    let step: GpioMotor = gpio_motor(step_pin, pulse)?;
+    let (dir_pin, _dir_reset_guard) = output_pin(dir_pin, false)?;
+
+    let mut motor: FloppyMotor = FloppyMotor {
+        head: StepDirMotor {
+            step,
+            dir_pin,
+            forward_high: false,
+            travel: Travel::from_start((tracks - 1) * MAX_MICROSTEPS as u64),
+            _dir_reset_guard,
+        },
+        select_pin,
+    };
+
+    motor.home();
+    Ok(motor)
+}
+
+#[cfg(feature = "raspi")]
+impl FloppyMotor {
+    /// Move the head back to track 0. The drive doesn't say which track the
+    /// head is on, but stepping backwards past track 0 does nothing, so
+    /// stepping back once for every track is always enough.
+    pub fn home(&mut self) {
+        set_pin(&self.head.dir_pin, !self.head.forward_high);
+
+        for _ in 0..=self.head.travel.max / self.head.step_size() as i64 {
+            self.head.step_slowly(FLOPPY_SEEK_TIME);
+        }
+
+        self.head.travel = Travel::from_start(self.head.travel.max as u64);
+        self.head.set_direction();
+    }
+}
+
+#[cfg(feature = "raspi")]
+impl Motor for FloppyMotor {
+    fn advance(&mut self) {
+        self.head.advance();
+    }
+
+    fn reset(&mut self) {
+        self.head.reset();
+    }
+
+    fn pulse(&self) -> Pulse {
+        self.head.pulse()
+    }
+
+    fn park(&mut self) {
+        self.head.go_back(FLOPPY_SEEK_TIME);
+    }
+}
+
+#[cfg(feature = "raspi")]
+impl Drop for FloppyMotor {
+    fn drop(&mut self) {
+        if let Some((pin, _)) = &self.select_pin {
+            lock_pin(pin).set_high();
+        }
+    }
+}
+
+/// A motor whose driver has an ENABLE pin, which powers the driver down
+/// between notes so that the motor and the driver don't heat up holding the
+/// motor still. ENABLE is active low, as it is on A4988, DRV8825 and TMC
+/// drivers, so it's driven high (disabled) when the motor is dropped, and if
+/// ambrose is interrupted or panics.
+#[cfg(feature = "raspi")]
+pub struct EnabledMotor<M: Motor> {
+    motor: M,
+    enable_pin: SharedPin,
+    idle_timeout_mcs: u64,
+    _enable_reset_guard: ResetGuard,
+}
+
+/// Give a motor an enable pin, which is released once the motor has been
+/// idle for the given time.
+#[cfg(feature = "raspi")]
+pub fn enabled_motor<M: Motor>(motor: M, enable_pin: u8, idle_timeout_mcs: u64) -> Result<EnabledMotor<M>, Box<dyn Error>> {
+    let (enable_pin, _enable_reset_guard) = output_pin(enable_pin, true)?;
+
+    Ok(EnabledMotor { motor, enable_pin, idle_timeout_mcs, _enable_reset_guard })
+}
+
+#[cfg(feature = "raspi")]
+impl<M: Motor> Motor for EnabledMotor<M> {
+    fn advance(&mut self) {
+        self.motor.advance();
+    }
+
+    fn reset(&mut self) {
+        self.motor.reset();
+    }
+
+    fn pulse(&self) -> Pulse {
+        self.motor.pulse()
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        set_pin(&self.enable_pin, !enabled);
+        self.motor.set_enabled(enabled);
+    }
+
+    fn idle_timeout_mcs(&self) -> Option<u64> {
+        Some(self.idle_timeout_mcs)
+    }
+
+    fn microsteps(&self) -> u32 {
+        self.motor.microsteps()
+    }
+
+    fn set_microsteps(&mut self, microsteps: u32) {
+        self.motor.set_microsteps(microsteps);
+    }
+
+    fn park(&mut self) {
+        self.set_enabled(true);
+        self.motor.park();
+        self.set_enabled(false);
+    }
+}
+
+#[cfg(feature = "raspi")]
+impl<M: Motor> Drop for EnabledMotor<M> {
+    fn drop(&mut self) {
+        self.set_enabled(false);
+    }
+}
+
+/// A motor whose driver is set to take microsteps. If ambrose has the
+/// driver's mode pins, voices can switch it between modes; otherwise, it's
+/// always in the mode that it's wired for. The mode pins are driven low (full
+/// steps) when the motor is dropped, and if ambrose is interrupted or panics.
+#[cfg(feature = "raspi")]
+pub struct MicrostepMotor<M: Motor> {
+    motor: M,
+    driver: MicrostepDriver,
+    mode_pins: Vec<(SharedPin, ResetGuard)>,
+    microsteps: u32,
+}
+
+/// Set up a motor to take the given number of microsteps per step, setting
+/// its driver's mode pins, if there are any.
+#[cfg(feature = "raspi")]
+pub fn microstep_motor<M: Motor>(
+    motor: M,
+    driver: MicrostepDriver,
+    mode_pins: &[u8],
+    microsteps: u32,
+) -> Result<MicrostepMotor<M>, Box<dyn Error>> {
+    let mode_pins: Vec<(SharedPin, ResetGuard)> = mode_pins.iter()
+        .map(|&pin_number| output_pin(pin_number, false))
+        .collect::<Result<_, _>>()?;
+
+    let mut motor: MicrostepMotor<M> = MicrostepMotor { motor, driver, mode_pins, microsteps: 1 };
+
+    if motor.mode_pins.is_empty() {
+        motor.microsteps = microsteps;
+    } else {
+        motor.set_microsteps(microsteps);
+
+        if motor.microsteps != microsteps {
+            return Err(format!("the driver doesn't have a mode with {} microsteps", microsteps).into());
+        }
+    }
+
+    Ok(motor)
+}
+
+#[cfg(feature = "raspi")]
+impl<M: Motor> Motor for MicrostepMotor<M> {
+    fn advance(&mut self) {
+        self.motor.advance();
+    }
+
+    fn reset(&mut self) {
+        self.motor.reset();
+    }
+
+    fn pulse(&self) -> Pulse {
+        self.motor.pulse()
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.motor.set_enabled(enabled);
+    }
+
+    fn idle_timeout_mcs(&self) -> Option<u64> {
+        self.motor.idle_timeout_mcs()
+    }
+
+    fn park(&mut self) {
+        self.motor.park();
+    }
+
+    fn microsteps(&self) -> u32 {
+        self.microsteps
+    }
+
+    fn set_microsteps(&mut self, microsteps: u32) {
+        let levels: [bool; 3] = match self.driver.mode_pin_levels(microsteps) {
+            Some(levels) if !self.mode_pins.is_empty() => levels,
+            _ => return,
+        };
+
+        for ((pin, _), &high) in self.mode_pins.iter().zip(&levels) {
+            set_pin(pin, high);
+        }
+
+        self.microsteps = microsteps;
+    }
+}
+
+#[cfg(feature = "raspi")]
+impl<M: Motor> Drop for MicrostepMotor<M> {
+    fn drop(&mut self) {
+        for (pin, _) in &self.mode_pins {
+            lock_pin(pin).set_low();
+        }
+    }
+}
+
+/// A motor without a step driver, such as a 28BYJ-48 on a ULN2003 board or a
+/// bipolar motor on an H-bridge, whose four coils are switched by GPIO pins.
+/// Each step moves it on to the next phase of its coil sequence. The coils are
+/// switched off while the motor is disabled, and when it's dropped, and if
+/// ambrose is interrupted or panics.
+#[cfg(feature = "raspi")]
+pub struct CoilMotor {
+    coils: Vec<(SharedPin, ResetGuard)>,
+    sequence: CoilSequence,
+    phase: usize,
+    enabled: bool,
+    idle_timeout_mcs: Option<u64>,
+}
+
+/// Set up a motor whose coils are on the given four GPIO pins, in the order
+/// that they're energized in.
+#[cfg(feature = "raspi")]
+pub fn coil_motor(coil_pins: &[u8], sequence: CoilSequence, idle_timeout_mcs: Option<u64>) -> Result<CoilMotor, Box<dyn Error>> {
+    if coil_pins.len() != 4 {
+        return Err(format!("a coil motor needs 4 pins, not {}", coil_pins.len()).into());
+    }
+
+    let coils: Vec<(SharedPin, ResetGuard)> = coil_pins.iter()
+        .map(|&pin_number| output_pin(pin_number, false))
+        .collect::<Result<_, _>>()?;
+
+    Ok(CoilMotor { coils, sequence, phase: 0, enabled: false, idle_timeout_mcs })
+}
+
+#[cfg(feature = "raspi")]
+impl CoilMotor {
+    /// Energize the coils for the current phase, or switch them all off if
+    /// the motor is disabled.
+    fn set_coils(&self) {
+        let levels: [bool; 4] = self.sequence.phases()[self.phase];
+
+        for ((pin, _), &high) in self.coils.iter().zip(&levels) {
+            set_pin(pin, high && self.enabled);
+        }
+    }
+}
+
+#[cfg(feature = "raspi")]
+impl Motor for CoilMotor {
+    fn advance(&mut self) {
+        self.phase = (self.phase + 1) % self.sequence.phases().len();
+        self.set_coils();
+    }
+
+    // The coils hold the motor where it is until the next step.
+    fn reset(&mut self) { }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.set_coils();
+    }
+
+    fn idle_timeout_mcs(&self) -> Option<u64> {
+        self.idle_timeout_mcs
+    }
+
+    fn microsteps(&self) -> u32 {
+        self.sequence.microsteps()
+    }
+}
+
+#[cfg(feature = "raspi")]
+impl Drop for CoilMotor {
+    fn drop(&mut self) {
+        for (pin, _) in &self.coils {
+            lock_pin(pin).set_low();
+        }
+    }
+}
+
+#[cfg(test)]
 pub struct TestMotor {
     count: u64,
 }
@@ -51,6 +863,7 @@ pub fn test_motor() -> TestMotor {
     TestMotor { count: 0 }
 }
 
+#[cfg(test)]
 impl Motor for TestMotor {
     fn advance(&mut self) {
         self.count += 1;
@@ -59,20 +872,29 @@ impl Motor for TestMotor {
     fn reset(&mut self) { }
 }
 
-#[cfg(not(feature = "raspi"))]
 #[derive(Clone)]
 pub struct SimpleAudioMotor {
     pub is_high: Rc<RefCell<bool>>,
+    pub pulse: Pulse,
 }
 
-#[cfg(not(feature = "raspi"))]
 impl SimpleAudioMotor {
     pub fn new() -> Self {
-        SimpleAudioMotor { is_high: Rc::new(RefCell::new(false)) }
+        SimpleAudioMotor::with_pulse(Pulse::default())
+    }
+
+    /// A motor that sounds like one stepped with the given pulse.
+    pub fn with_pulse(pulse: Pulse) -> Self {
+        SimpleAudioMotor { is_high: Rc::new(RefCell::new(false)), pulse }
+    }
+}
+
+impl Default for SimpleAudioMotor {
+    fn default() -> Self {
+        SimpleAudioMotor::new()
     }
 }
 
-#[cfg(not(feature = "raspi"))]
 impl Motor for SimpleAudioMotor {
     fn advance(&mut self) {
         *(self.is_high.borrow_mut()) = true;
@@ -81,6 +903,10 @@ impl Motor for SimpleAudioMotor {
     fn reset(&mut self) {
         *(self.is_high.borrow_mut()) = false;
     }
+
+    fn pulse(&self) -> Pulse {
+        self.pulse
+    }
 }
 
 #[cfg(test)]
@@ -90,10 +916,90 @@ mod tests {
     #[cfg(feature = "raspi")]
     #[test]
     fn can_create_gpio_motor() -> Result<(), Box<dyn Error>> {
-        let _motor: GpioMotor = gpio_motor(14)?;
+        let _motor: GpioMotor = gpio_motor(14, Pulse::default())?;
         Ok(())
     }
 
+    #[test]
+    fn turns_around_at_the_ends_of_travel() {
+        let mut travel: Travel = Travel::new(4);
+        let turns: Vec<bool> = (0..8).map(|_| travel.step()).collect();
+
+        // From the middle, it's two steps to the end, four steps back to the
+        // other end, and two steps back to the middle.
+        assert_eq!(turns, vec![false, true, false, false, false, true, false, false]);
+        assert_eq!(travel.position(), 0);
+        assert!(travel.is_forward());
+
+        // Back at the start, there's nothing to go back.
+        assert_eq!(travel.turn_back(), 0);
+
+        travel.step();
+        travel.step();
+        travel.step();
+        assert_eq!(travel.position(), 1);
+
+        // Going back to the start turns around, without reaching the end.
+        assert_eq!(travel.turn_back(), 1);
+        assert!(!travel.is_forward());
+        assert!(!travel.step());
+        assert_eq!(travel.position(), 0);
+
+        // From past the middle the other way, it goes forwards.
+        travel.step();
+        assert_eq!(travel.turn_back(), 1);
+        assert!(travel.is_forward());
+
+        let mut travel: Travel = Travel::from_start(2);
+        let turns: Vec<bool> = (0..4).map(|_| travel.step()).collect();
+        assert_eq!(turns, vec![false, true, false, true]);
+    }
+
+    #[test]
+    fn never_steps_past_the_end_in_coarser_modes() {
+        let mut travel: Travel = Travel::new(8);
+        assert!(!travel.step_by(1));
+        assert!(!travel.step_by(1));
+        assert!(!travel.step_by(1));
+
+        // A step of 2 from 3 would end up past the end at 4, so switching to
+        // a coarser mode turns the carriage around first.
+        assert!(travel.prepare_step(2));
+        assert!(!travel.is_forward());
+
+        assert!(!travel.step_by(2));
+        assert!(!travel.step_by(2));
+        assert!(travel.step_by(2));
+        assert_eq!(travel.position(), -3);
+        assert!(travel.is_forward());
+    }
+
+    #[test]
+    fn knows_microstep_modes() {
+        assert_eq!(MicrostepDriver::A4988.mode_pin_levels(8), Some([true, true, false]));
+        assert_eq!(MicrostepDriver::A4988.mode_pin_levels(16), Some([true, true, true]));
+        assert_eq!(MicrostepDriver::A4988.mode_pin_levels(32), None);
+        assert_eq!(MicrostepDriver::Drv8825.mode_pin_levels(16), Some([false, false, true]));
+        assert_eq!(MicrostepDriver::Drv8825.mode_pin_levels(3), None);
+    }
+
+    #[test]
+    fn steps_through_coil_sequences() {
+        for sequence in [CoilSequence::Wave, CoilSequence::Full, CoilSequence::Half].iter() {
+            let phases: &[[bool; 4]] = sequence.phases();
+
+            // Each phase changes one coil in a half-step sequence, and two
+            // otherwise, including when the sequence goes back to the start.
+            for (phase, next_phase) in phases.iter().zip(phases.iter().cycle().skip(1)) {
+                let changes: u32 = phase.iter().zip(next_phase).filter(|(a, b)| a != b).count() as u32;
+                assert_eq!(changes, 2 / sequence.microsteps());
+            }
+        }
+
+        assert_eq!(CoilSequence::Half.phases().len(), 8);
+        assert_eq!(CoilSequence::Full.phases()[0], [true, true, false, false]);
+    }
+
     #[test]
     fn can_create_test_motor() {
         let mut motor: TestMotor = test_motor();
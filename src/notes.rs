@@ -1,17 +1,85 @@
 use std::error::Error;
 
-use crate::motor::Motor;
+use crate::control::{
+    ControlAction,
+    PlaybackControl,
+};
+
+use crate::motor::{
+    Motor,
+    Pulse,
+};
 
 use crate::timer::Timer;
 
-#[derive(Copy, Clone)]
+use crate::validate::check_song;
+
+#[derive(Copy, Clone, Debug)]
 pub struct NoteInfo {
     pub next_note_index: u32,
     pub motor_id: u8,
+    /// Ends the voice when it reaches this note, which isn't played. The song
+    /// ends once all of its voices have.
     pub exit: bool,
+    /// Makes an exit note end the whole song, cutting off any voices that are
+    /// still playing. Only exit notes can be stop notes.
+    pub stop: bool,
     pub frequency_mchz: u64,
     pub length_mcs: u64,
     pub rearticulate: bool,
+    /// Go back to an earlier note when this one ends, to repeat a section.
+    pub repeat: Option<Repeat>,
+}
+
+/// A repeated section of a voice, which ends at the note that has the repeat.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Repeat {
+    /// The first note of the section.
+    pub note_index: u32,
+    /// How many times the section is played in all, so 2 plays it twice.
+    pub times: u32,
+}
+
+/// Keeps track of how many more times a voice has to go back for each of the
+/// repeats that it's in the middle of.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct RepeatCounter {
+    times_left: Vec<(u32, u32)>,
+}
+
+impl RepeatCounter {
+    /// The index of the note that the voice plays after the given one. When a
+    /// repeat has been played enough times, its count is forgotten, so that a
+    /// repeat nested inside another starts again each time the outer section
+    /// does.
+    pub fn next_note_index(&mut self, note_index: u32, note: &NoteInfo) -> u32 {
+        let repeat: Repeat = match note.repeat {
+            Some(repeat) if repeat.times > 1 => repeat,
+            _ => return note.next_note_index,
+        };
+
+        let position: usize = match self.times_left.iter().position(|&(i, _)| i == note_index) {
+            Some(position) => position,
+            None => {
+                self.times_left.push((note_index, repeat.times - 1));
+                self.times_left.len() - 1
+            }
+        };
+
+        if self.times_left[position].1 > 0 {
+            self.times_left[position].1 -= 1;
+            repeat.note_index
+        } else {
+            self.times_left.remove(position);
+            note.next_note_index
+        }
+    }
+
+    /// Whether the voice is in the middle of playing the repeat that ends at
+    /// the given note.
+    pub fn is_repeating(&self, note_index: u32) -> bool {
+        self.times_left.iter().any(|&(i, _)| i == note_index)
+    }
 }
 
 impl NoteInfo {
@@ -30,63 +98,829 @@ impl NoteInfo {
     pub fn exit(self) -> Self {
         NoteInfo { exit: true, ..self }
     }
+
+    pub fn stop(self) -> Self {
+        NoteInfo { exit: true, stop: true, ..self }
+    }
 }
 
 pub struct Voice {
     note_index: u32,
-    microseconds: u64,
     phase: u64,
+    is_high: bool,
+    note_end_ns: u64,
+    repeats: RepeatCounter,
+    microsteps: Option<u32>,
+    /// How fast the voice's motor is being stepped, which is the frequency of
+    /// its note times the motor's microsteps.
+    step_frequency_mchz: u64,
+}
+
+impl Voice {
+    /// The index of the note that the voice is on.
+    pub fn note_index(&self) -> u32 {
+        self.note_index
+    }
+
+    /// The microstep mode that the voice plays its motors in, if it has one.
+    pub fn microsteps(&self) -> Option<u32> {
+        self.microsteps
+    }
+
+    /// Play the voice's motors in the given microstep mode, for motors that
+    /// can switch modes.
+    pub fn with_microsteps(self, microsteps: Option<u32>) -> Self {
+        Voice { microsteps, ..self }
+    }
+
+    /// The time of the voice's next step edge, or `u64::MAX` if it's resting,
+    /// given how much of each cycle the pin is high for.
+    fn next_edge_ns(&self, high_phase: u64, now_ns: u64) -> u64 {
+        if self.step_frequency_mchz == 0 {
+            return u64::MAX;
+        }
+
+        let boundary: u64 = if self.phase < high_phase { high_phase } else { CYCLE };
+        now_ns + (boundary - self.phase).div_ceil(self.step_frequency_mchz)
+    }
 }
 
 pub fn voice(note_index: u32) -> Voice {
-    Voice { note_index, microseconds: 0, phase: 500_000_000_000 }
+    Voice {
+        note_index,
+        phase: HALF_CYCLE,
+        is_high: false,
+        note_end_ns: 0,
+        repeats: RepeatCounter::default(),
+        microsteps: None,
+        step_frequency_mchz: 0,
+    }
 }
 
-const TICK_FREQUENCY_HZ: u64 = 50000;
-const TICK_DURATION_MCS: u64 = 1000000 / TICK_FREQUENCY_HZ;
+// Phases are measured in femtocycles, so that a voice's phase advances by its
+// frequency in microhertz every nanosecond, and the time of each step edge can
+// be worked out exactly (to the nanosecond) instead of waiting for a tick.
+const CYCLE: u64 = 1_000_000_000_000_000;
+const HALF_CYCLE: u64 = CYCLE / 2;
+
+/// How much of each cycle, in femtocycles, a pin is high for with the given
+/// pulse at the given frequency. The pin goes high at the start of the cycle.
+fn high_phase(pulse: Pulse, frequency_mchz: u64) -> u64 {
+    match pulse {
+        Pulse::Duty { percent } => (CYCLE as f64 * percent / 100.0) as u64,
+        Pulse::Width { mcs } => (mcs as u128 * 1000 * frequency_mchz as u128).min(HALF_CYCLE as u128) as u64,
+    }
+}
+
+/// Switch a motor to a voice's microstep mode, if the voice has one and the
+/// motor can switch to it, and return the mode that the motor is in. Modes are
+/// only switched between steps, so nothing changes while the pin is high.
+fn voice_microsteps<M: Motor>(pin: &mut M, microsteps: Option<u32>, is_high: bool) -> u32 {
+    if let Some(microsteps) = microsteps {
+        if pin.microsteps() != microsteps && !is_high {
+            pin.set_microsteps(microsteps);
+        }
+    }
+
+    pin.microsteps()
+}
+
+/// Put each voice where it would be at the given time, as if it had been
+/// playing from its first note, switching the motors to each voice's
+/// microstep mode as it goes. The motors' pins have to be low. Returns false
+/// if the song would already have ended by then.
+fn seek_voices<M: Motor>(
+    notes: &[NoteInfo],
+    voices: &mut [Voice],
+    first_note_indices: &[u32],
+    pins: &mut [M],
+    time_ns: u64,
+) -> bool {
+    for (voice, &first_note_index) in voices.iter_mut().zip(first_note_indices) {
+        let mut note_index: u32 = first_note_index;
+        let mut note_start_ns: u64 = 0;
+        let mut phase: u128 = HALF_CYCLE as u128;
+        let mut repeats: RepeatCounter = RepeatCounter::default();
+        let mut first_note: bool = true;
+
+        loop {
+            let note: NoteInfo = notes[note_index as usize];
+
+            if note.stop {
+                return false;
+            }
+
+            // Rests can be on motors that don't exist.
+            let step_frequency_mchz: u128 = match note.frequency_mchz {
+                0 => 0,
+                frequency_mchz => {
+                    let pin: &mut M = &mut pins[note.motor_id as usize];
+                    frequency_mchz as u128 * voice_microsteps(pin, voice.microsteps, false) as u128
+                }
+            };
+
+            // A voice that loops or repeats back to its first note plays it
+            // again like any other note.
+            if !first_note && note.rearticulate {
+                phase += HALF_CYCLE as u128;
+            }
+
+            let note_end_ns: u64 = note_start_ns + note.length_mcs * 1000;
+
+            if note.exit || note_end_ns > time_ns {
+                if !note.exit {
+                    phase += step_frequency_mchz * (time_ns - note_start_ns) as u128;
+                }
+
+                *voice = Voice {
+                    note_index,
+                    phase: (phase % CYCLE as u128) as u64,
+                    is_high: false,
+                    note_end_ns,
+                    repeats,
+                    microsteps: voice.microsteps,
+                    step_frequency_mchz: 0,
+                };
+                break;
+            }
+
+            phase = (phase + step_frequency_mchz * (note_end_ns - note_start_ns) as u128) % CYCLE as u128;
+            note_index = repeats.next_note_index(note_index, &note);
+            note_start_ns = note_end_ns;
+            first_note = false;
+        }
+    }
+
+    !voices.iter().all(|v| notes[v.note_index as usize].exit)
+}
+
+/// Disable all of the motors that are enabled.
+fn disable_motors<M: Motor>(pins: &mut [M], enabled: &mut [bool]) {
+    for (pin, enabled) in pins.iter_mut().zip(enabled) {
+        if *enabled {
+            pin.set_enabled(false);
+            *enabled = false;
+        }
+    }
+}
 
 pub fn play_note_info_array<M: Motor, T: Timer>(
+    pins: Vec<M>,
+    notes: Vec<NoteInfo>,
+    voices: Vec<Voice>,
+    timer: &mut T
+) -> Result<(), Box<dyn Error>> {
+    play_note_info_array_with_control(pins, notes, voices, timer, &PlaybackControl::new())
+}
+
+/// Play a song, letting `control` pause, resume, stop or seek it while it
+/// plays. The motors are reset whenever the song is paused or stopped.
+///
+/// Each motor is enabled before it plays a note, and disabled once it has
+/// been idle for its idle timeout, or when the song is paused or ends.
+pub fn play_note_info_array_with_control<M: Motor, T: Timer>(
     mut pins: Vec<M>,
     notes: Vec<NoteInfo>,
     mut voices: Vec<Voice>,
-    timer: &mut T
+    timer: &mut T,
+    control: &PlaybackControl,
 ) -> Result<(), Box<dyn Error>> {
+    check_song(&notes, &voices, Some(pins.len()))?;
+
     for pin in &mut *pins { pin.reset(); }
     timer.reset()?;
 
+    let first_note_indices: Vec<u32> = voices.iter().map(|v| v.note_index).collect();
+
+    for voice in &mut *voices {
+        voice.note_end_ns = notes[voice.note_index as usize].length_mcs * 1000;
+    }
+
+    let mut now_ns: u64 = 0;
+    let mut waited_mcs: u64 = 0;
+
+    let mut enabled: Vec<bool> = vec![false; pins.len()];
+    let mut playing: Vec<bool> = vec![false; pins.len()];
+    let mut idle_since_ns: Vec<Option<u64>> = vec![None; pins.len()];
+
     loop {
-        timer.wait_microseconds(TICK_DURATION_MCS)?;
+        if control.has_pending() {
+            let action: ControlAction = control.take_action(|| {
+                for pin in &mut *pins { pin.reset(); }
+                for voice in &mut *voices { voice.is_high = false; }
+                disable_motors(&mut pins, &mut enabled);
+            });
+
+            match action {
+                ControlAction::Continue => { }
+                ControlAction::Stop => {
+                    for pin in &mut *pins { pin.reset(); }
+                    disable_motors(&mut pins, &mut enabled);
+                    return Ok(());
+                }
+                ControlAction::Seek(time_mcs) => {
+                    for pin in &mut *pins { pin.reset(); }
+
+                    if !seek_voices(&notes, &mut voices, &first_note_indices, &mut pins, time_mcs * 1000) {
+                        disable_motors(&mut pins, &mut enabled);
+                        return Ok(());
+                    }
+
+                    idle_since_ns.fill(None);
+
+                    now_ns = time_mcs * 1000;
+                    waited_mcs = time_mcs;
+                }
+            }
+
+            // Don't try to catch up on the time spent paused or seeking.
+            timer.reset()?;
+        }
+
+        control.set_position_mcs(waited_mcs);
+
+        let mut next_ns: u64 = u64::MAX;
+        let mut stopped: bool = false;
+        playing.fill(false);
 
         for voice in &mut *voices {
-            // println!("playing note {}", voice.note_index);
-            let note: NoteInfo = notes[voice.note_index as usize];
+            let mut note: NoteInfo = notes[voice.note_index as usize];
+
+            while now_ns >= voice.note_end_ns && !note.exit {
+                let next_note_index: u32 = voice.repeats.next_note_index(voice.note_index, &note);
+                let next_note: NoteInfo = notes[next_note_index as usize];
+
+                // A voice that ends lets go of its motor.
+                if (next_note.exit || next_note.motor_id != note.motor_id) && voice.is_high {
+                    pins[note.motor_id as usize].reset();
+                    voice.is_high = false;
+                }
+
+                if next_note.rearticulate {
+                    voice.phase = (voice.phase + HALF_CYCLE) % CYCLE;
+                }
+
+                voice.note_index = next_note_index;
+                voice.note_end_ns += next_note.length_mcs * 1000;
+                note = next_note;
+            }
+
+            if note.stop {
+                stopped = true;
+                break;
+            }
 
             if note.exit {
-                return Ok(());
+                continue;
             }
 
-            let increment: u64 = note.frequency_mchz * TICK_DURATION_MCS;
-            voice.phase = (voice.phase + increment) % 1_000_000_000_000;
+            let motor_id: usize = note.motor_id as usize;
 
-            let motor: &mut M = &mut pins[note.motor_id as usize];
+            // Rests keep the motor's pin low, so a voice that's resting never
+            // touches its motor, which might not even exist.
+            let high_phase: u64 = if note.frequency_mchz > 0 {
+                playing[motor_id] = true;
 
-            if voice.phase < 500_000_000_000 {
-                motor.advance();
+                if !enabled[motor_id] {
+                    pins[motor_id].set_enabled(true);
+                    enabled[motor_id] = true;
+                }
+
+                // The motor is stepped faster in finer microstep modes, so
+                // that it turns at the same speed, and plays the same note.
+                let microsteps: u32 = voice_microsteps(&mut pins[motor_id], voice.microsteps, voice.is_high);
+                voice.step_frequency_mchz = note.frequency_mchz * microsteps as u64;
+                high_phase(pins[motor_id].pulse(), voice.step_frequency_mchz)
             } else {
-                motor.reset();
+                voice.step_frequency_mchz = 0;
+                0
+            };
+
+            let should_be_high: bool = voice.phase < high_phase;
+
+            if should_be_high != voice.is_high {
+                let motor: &mut M = &mut pins[note.motor_id as usize];
+
+                if should_be_high {
+                    motor.advance();
+                } else {
+                    motor.reset();
+                }
+
+                voice.is_high = should_be_high;
+            }
+
+            next_ns = next_ns.min(voice.note_end_ns).min(voice.next_edge_ns(high_phase, now_ns));
+        }
+
+        // A stop note cuts off the voices that are still holding their motors'
+        // pins high. The others have already let go of them.
+        if stopped {
+            for voice in voices.iter().filter(|v| v.is_high) {
+                pins[notes[voice.note_index as usize].motor_id as usize].reset();
+            }
+
+            disable_motors(&mut pins, &mut enabled);
+            return Ok(());
+        }
+
+        // Every voice has ended.
+        if next_ns == u64::MAX {
+            disable_motors(&mut pins, &mut enabled);
+            return Ok(());
+        }
+
+        // A motor is idle while none of the voices are playing a note on it,
+        // and once it has been idle for long enough, it's disabled. If that's
+        // before the next edge, wake up for it.
+        for (motor_id, pin) in pins.iter_mut().enumerate() {
+            if playing[motor_id] {
+                idle_since_ns[motor_id] = None;
+                continue;
+            }
+
+            let idle_since: u64 = *idle_since_ns[motor_id].get_or_insert(now_ns);
+
+            if !enabled[motor_id] {
+                continue;
             }
 
-            voice.microseconds += TICK_DURATION_MCS;
-            if voice.microseconds >= note.length_mcs {
-                voice.note_index = note.next_note_index;
-                voice.microseconds -= note.length_mcs;
+            if let Some(timeout_mcs) = pin.idle_timeout_mcs() {
+                let disable_ns: u64 = idle_since + timeout_mcs * 1000;
 
-                if notes[voice.note_index as usize].rearticulate {
-                    voice.phase = (voice.phase + 500_000_000_000) % 1_000_000_000_000;
+                if now_ns >= disable_ns {
+                    pin.set_enabled(false);
+                    enabled[motor_id] = false;
+                } else {
+                    next_ns = next_ns.min(disable_ns);
                 }
+            }
+        }
+
+        // Waiting for the rounded time of each event, rather than the rounded
+        // time between events, keeps rounding errors from adding up.
+        let next_mcs: u64 = (next_ns + 500) / 1000;
+
+        if next_mcs > waited_mcs {
+            timer.wait_microseconds(next_mcs - waited_mcs)?;
+            waited_mcs = next_mcs;
+        }
+
+        for voice in voices.iter_mut().filter(|v| !notes[v.note_index as usize].exit) {
+            voice.phase = (voice.phase + voice.step_frequency_mchz * (next_ns - now_ns)) % CYCLE;
+        }
+
+        now_ns = next_ns;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::thread;
+    use std::time::Duration;
 
-                // println!("moving on to note {}, frequency {}", voice.note_index, notes[voice.note_index as usize].frequency_mchz);
+    use crate::motor::*;
+    use crate::notes::*;
+    use crate::timer::*;
+
+    type Edges = Rc<RefCell<Vec<(u64, bool)>>>;
+
+    /// A motor that records the time of each of its edges, according to a
+    /// `RecordingTimer`.
+    struct RecordingMotor {
+        time_mcs: Rc<RefCell<u64>>,
+        edges: Edges,
+        pulse: Pulse,
+    }
+
+    impl Motor for RecordingMotor {
+        fn advance(&mut self) {
+            self.edges.borrow_mut().push((*self.time_mcs.borrow(), true));
+        }
+
+        fn reset(&mut self) {
+            self.edges.borrow_mut().push((*self.time_mcs.borrow(), false));
+        }
+
+        fn pulse(&self) -> Pulse {
+            self.pulse
+        }
+    }
+
+    /// A timer that keeps track of the time, and can make a request through a
+    /// `PlaybackControl` once a given time is reached.
+    struct RecordingTimer {
+        time_mcs: Rc<RefCell<u64>>,
+        request: Option<(u64, Box<dyn Fn()>)>,
+    }
+
+    impl Timer for RecordingTimer {
+        fn wait_microseconds(&mut self, duration: u64) -> Result<(), Box<dyn Error>> {
+            *self.time_mcs.borrow_mut() += duration;
+
+            if let Some((request_time_mcs, _)) = &self.request {
+                if *self.time_mcs.borrow() >= *request_time_mcs {
+                    (self.request.take().unwrap().1)();
+                }
             }
+
+            Ok(())
         }
+
+        fn reset(&mut self) -> Result<(), Box<dyn Error>> { Ok(()) }
+    }
+
+    fn note(frequency_hz: u64, length_mcs: u64) -> NoteInfo {
+        NoteInfo {
+            next_note_index: 1,
+            motor_id: 0,
+            exit: false,
+            stop: false,
+            frequency_mchz: frequency_hz * 1_000_000,
+            length_mcs,
+            rearticulate: true,
+            repeat: None,
+        }
+    }
+
+    /// Play a one-voice song and return the motor's edges, not counting the
+    /// reset at the start. If `request` is given, it's called once the given
+    /// time is reached.
+    fn controlled_edges(
+        notes: Vec<NoteInfo>,
+        control: &PlaybackControl,
+        request: Option<(u64, Box<dyn Fn()>)>,
+    ) -> Vec<(u64, bool)> {
+        let time_mcs: Rc<RefCell<u64>> = Rc::new(RefCell::new(0));
+        let edges: Edges = Rc::new(RefCell::new(vec![]));
+        let motor: RecordingMotor = RecordingMotor { time_mcs: time_mcs.clone(), edges: edges.clone(), pulse: Pulse::default() };
+        let mut timer: RecordingTimer = RecordingTimer { time_mcs, request };
+
+        play_note_info_array_with_control(vec![motor], notes, vec![voice(0)], &mut timer, control).unwrap();
+
+        let result: Vec<(u64, bool)> = edges.borrow()[1..].to_vec();
+        result
+    }
+
+    fn edges(notes: Vec<NoteInfo>) -> Vec<(u64, bool)> {
+        controlled_edges(notes, &PlaybackControl::new(), None)
+    }
+
+    #[test]
+    fn can_play_until_exit() -> Result<(), Box<dyn Error>> {
+        let pins: Vec<TestMotor> = vec![test_motor()];
+        let notes: Vec<NoteInfo> = vec![note(440, 10_000), note(440, 10_000).exit()];
+
+        play_note_info_array(pins, notes, vec![voice(0)], &mut DummyTimer { })
+    }
+
+    #[test]
+    fn steps_at_exact_times() {
+        // The motor is let go of when the voice ends.
+        assert_eq!(edges(vec![note(1000, 2000), note(0, 0).exit()]), vec![
+            (500, true), (1000, false), (1500, true), (2000, false),
+        ]);
+
+        // At 3 kHz, the edges don't fall on whole microseconds, but they
+        // shouldn't drift either.
+        let edges: Vec<(u64, bool)> = edges(vec![note(3000, 1_000_000), note(0, 0).exit()]);
+        assert_eq!(edges.len(), 6000);
+        assert_eq!(edges[0], (167, true));
+        assert_eq!(edges[5998], (999_833, true));
+    }
+
+    #[test]
+    fn rearticulates_notes() {
+        let notes: Vec<NoteInfo> = vec![
+            note(1000, 700),
+            NoteInfo { next_note_index: 2, ..note(1000, 700) },
+            NoteInfo { next_note_index: 3, ..note(1000, 600).slur() },
+            note(0, 0).exit(),
+        ];
+
+        // The kick at 700 microseconds turns the motor off half a cycle early,
+        // and the slur at 1400 microseconds doesn't do anything.
+        assert_eq!(edges(notes), vec![
+            (500, true), (700, false), (1000, true), (1500, false),
+        ]);
+    }
+
+    #[test]
+    fn rests_keep_the_pin_low() {
+        let notes: Vec<NoteInfo> = vec![
+            note(1000, 700),
+            NoteInfo { next_note_index: 2, ..note(0, 1000) },
+            note(0, 0).exit(),
+        ];
+
+        assert_eq!(edges(notes), vec![(500, true), (700, false)]);
+    }
+
+    #[test]
+    fn plays_repeats() {
+        let notes: Vec<NoteInfo> = vec![
+            note(1000, 1000),
+            NoteInfo { next_note_index: 2, repeat: Some(Repeat { note_index: 0, times: 2 }), ..note(0, 1000) },
+            note(0, 0).exit(),
+        ];
+
+        assert_eq!(edges(notes), vec![(500, true), (1000, false), (2500, true), (3000, false)]);
+    }
+
+    #[test]
+    fn nests_repeats() {
+        // Notes 0 and 1 are played twice, then note 2, and then all of that
+        // again.
+        let notes: Vec<NoteInfo> = vec![
+            note(1000, 1000),
+            NoteInfo { next_note_index: 2, repeat: Some(Repeat { note_index: 0, times: 2 }), ..note(1000, 1000) },
+            NoteInfo { next_note_index: 3, repeat: Some(Repeat { note_index: 0, times: 2 }), ..note(1000, 1000) },
+            note(0, 0).exit(),
+        ];
+
+        let mut repeats: RepeatCounter = RepeatCounter::default();
+        let mut played: Vec<u32> = vec![0];
+
+        while !notes[*played.last().unwrap() as usize].exit {
+            let index: u32 = *played.last().unwrap();
+            played.push(repeats.next_note_index(index, &notes[index as usize]));
+        }
+
+        assert_eq!(played, vec![0, 1, 0, 1, 2, 0, 1, 0, 1, 2, 3]);
+    }
+
+    /// Play a song on motors with the given pulses and return each motor's
+    /// edges, not counting the resets at the start.
+    fn motor_edges(notes: Vec<NoteInfo>, voices: Vec<Voice>, pulses: &[Pulse]) -> Vec<Vec<(u64, bool)>> {
+        let time_mcs: Rc<RefCell<u64>> = Rc::new(RefCell::new(0));
+        let edges: Vec<Edges> = pulses.iter().map(|_| Rc::new(RefCell::new(vec![]))).collect();
+        let motors: Vec<RecordingMotor> = edges.iter()
+            .zip(pulses)
+            .map(|(e, &pulse)| RecordingMotor { time_mcs: time_mcs.clone(), edges: e.clone(), pulse })
+            .collect();
+        let mut timer: RecordingTimer = RecordingTimer { time_mcs, request: None };
+
+        play_note_info_array(motors, notes, voices, &mut timer).unwrap();
+
+        edges.iter().map(|e| e.borrow()[1..].to_vec()).collect()
+    }
+
+    #[test]
+    fn shapes_pulses() {
+        let notes = || vec![note(1000, 2000), note(0, 0).exit()];
+
+        assert_eq!(motor_edges(notes(), vec![voice(0)], &[Pulse::Duty { percent: 25.0 }]), vec![
+            vec![(500, true), (750, false), (1500, true), (1750, false)],
+        ]);
+
+        assert_eq!(motor_edges(notes(), vec![voice(0)], &[Pulse::Width { mcs: 10 }]), vec![
+            vec![(500, true), (510, false), (1500, true), (1510, false)],
+        ]);
+
+        // A pulse that's too long for the frequency makes a square wave.
+        assert_eq!(motor_edges(notes(), vec![voice(0)], &[Pulse::Width { mcs: 800 }]), vec![
+            vec![(500, true), (1000, false), (1500, true), (2000, false)],
+        ]);
+    }
+
+    #[test]
+    fn voices_end_on_their_own() {
+        let notes: Vec<NoteInfo> = vec![
+            note(1000, 1000),
+            note(0, 0).exit(),
+            NoteInfo { next_note_index: 3, motor_id: 1, ..note(1000, 3000) },
+            NoteInfo { motor_id: 1, ..note(0, 0).exit() },
+        ];
+
+        assert_eq!(motor_edges(notes, vec![voice(0), voice(2)], &[Pulse::default(); 2]), vec![
+            vec![(500, true), (1000, false)],
+            vec![(500, true), (1000, false), (1500, true), (2000, false), (2500, true), (3000, false)],
+        ]);
+    }
+
+    #[test]
+    fn rests_can_be_on_missing_motors() {
+        // A muted voice on a machine with fewer motors than voices.
+        let notes: Vec<NoteInfo> = vec![
+            note(1000, 1000),
+            note(0, 0).exit(),
+            NoteInfo { next_note_index: 3, motor_id: 1, ..note(0, 2000) },
+            NoteInfo { motor_id: 1, ..note(0, 0).exit() },
+        ];
+
+        check_song(&notes, &[voice(0), voice(2)], Some(1)).unwrap();
+
+        assert_eq!(motor_edges(notes, vec![voice(0), voice(2)], &[Pulse::default()]), vec![
+            vec![(500, true), (1000, false)],
+        ]);
+    }
+
+    #[test]
+    fn stop_notes_end_the_song() {
+        let notes: Vec<NoteInfo> = vec![
+            note(1000, 1000),
+            note(0, 0).stop(),
+            NoteInfo { next_note_index: 3, motor_id: 1, ..note(1000, 3000) },
+            NoteInfo { motor_id: 1, ..note(0, 0).exit() },
+        ];
+
+        assert_eq!(motor_edges(notes, vec![voice(0), voice(2)], &[Pulse::default(); 2]), vec![
+            vec![(500, true), (1000, false)],
+            vec![(500, true), (1000, false)],
+        ]);
+    }
+
+    /// A motor that records the times that it's enabled and disabled.
+    struct PoweredMotor {
+        time_mcs: Rc<RefCell<u64>>,
+        power: Edges,
+        idle_timeout_mcs: Option<u64>,
+    }
+
+    impl Motor for PoweredMotor {
+        fn advance(&mut self) { }
+
+        fn reset(&mut self) { }
+
+        fn set_enabled(&mut self, enabled: bool) {
+            self.power.borrow_mut().push((*self.time_mcs.borrow(), enabled));
+        }
+
+        fn idle_timeout_mcs(&self) -> Option<u64> {
+            self.idle_timeout_mcs
+        }
+    }
+
+    #[test]
+    fn disables_idle_motors() {
+        let notes: Vec<NoteInfo> = vec![
+            note(1000, 1000),
+            NoteInfo { next_note_index: 2, ..note(0, 500) },
+            NoteInfo { next_note_index: 3, ..note(1000, 1000) },
+            NoteInfo { next_note_index: 4, ..note(0, 3000) },
+            NoteInfo { next_note_index: 5, ..note(1000, 1000) },
+            note(0, 0).exit(),
+        ];
+
+        let power = |idle_timeout_mcs: Option<u64>| {
+            let time_mcs: Rc<RefCell<u64>> = Rc::new(RefCell::new(0));
+            let power: Edges = Rc::new(RefCell::new(vec![]));
+            let motor: PoweredMotor = PoweredMotor { time_mcs: time_mcs.clone(), power: power.clone(), idle_timeout_mcs };
+            let mut timer: RecordingTimer = RecordingTimer { time_mcs, request: None };
+
+            play_note_info_array(vec![motor], notes.clone(), vec![voice(0)], &mut timer).unwrap();
+
+            let result: Vec<(u64, bool)> = power.borrow().clone();
+            result
+        };
+
+        // The short rest isn't long enough for the motor to be disabled, but
+        // the long one is.
+        assert_eq!(power(Some(1000)), vec![(0, true), (3500, false), (5500, true), (6500, false)]);
+        assert_eq!(power(None), vec![(0, true), (6500, false)]);
+    }
+
+    /// A motor that records its edges, and can switch between up to 4
+    /// microsteps.
+    struct MicrosteppingMotor {
+        time_mcs: Rc<RefCell<u64>>,
+        edges: Edges,
+        microsteps: u32,
+    }
+
+    impl Motor for MicrosteppingMotor {
+        fn advance(&mut self) {
+            self.edges.borrow_mut().push((*self.time_mcs.borrow(), true));
+        }
+
+        fn reset(&mut self) {
+            self.edges.borrow_mut().push((*self.time_mcs.borrow(), false));
+        }
+
+        fn microsteps(&self) -> u32 {
+            self.microsteps
+        }
+
+        fn set_microsteps(&mut self, microsteps: u32) {
+            if microsteps <= 4 {
+                self.microsteps = microsteps;
+            }
+        }
+    }
+
+    #[test]
+    fn scales_steps_for_microsteps() {
+        let notes: Vec<NoteInfo> = vec![note(1000, 1000), note(0, 0).exit()];
+
+        let edges = |voice: Voice| {
+            let time_mcs: Rc<RefCell<u64>> = Rc::new(RefCell::new(0));
+            let edges: Edges = Rc::new(RefCell::new(vec![]));
+            let motor: MicrosteppingMotor = MicrosteppingMotor { time_mcs: time_mcs.clone(), edges: edges.clone(), microsteps: 2 };
+            let mut timer: RecordingTimer = RecordingTimer { time_mcs, request: None };
+
+            play_note_info_array(vec![motor], notes.clone(), vec![voice], &mut timer).unwrap();
+
+            let result: Vec<u64> = edges.borrow()[1..].iter().filter(|e| e.1).map(|e| e.0).collect();
+            result
+        };
+
+        assert_eq!(edges(voice(0)), vec![250, 750]);
+        assert_eq!(edges(voice(0).with_microsteps(Some(4))), vec![125, 375, 625, 875]);
+
+        // The motor can't switch to 8 microsteps, so it stays in its own mode.
+        assert_eq!(edges(voice(0).with_microsteps(Some(8))), vec![250, 750]);
+    }
+
+    #[test]
+    fn seeks_in_each_voices_microstep_mode() {
+        let notes: Vec<NoteInfo> = vec![note(1000, 2000), note(0, 0).exit()];
+
+        let edges = |seek_mcs: u64| {
+            let time_mcs: Rc<RefCell<u64>> = Rc::new(RefCell::new(0));
+            let edges: Edges = Rc::new(RefCell::new(vec![]));
+            let motor: MicrosteppingMotor = MicrosteppingMotor { time_mcs: time_mcs.clone(), edges: edges.clone(), microsteps: 1 };
+            let mut timer: RecordingTimer = RecordingTimer { time_mcs, request: None };
+            let control: PlaybackControl = PlaybackControl::new();
+
+            control.seek(seek_mcs);
+            let voices: Vec<Voice> = vec![voice(0).with_microsteps(Some(4))];
+            play_note_info_array_with_control(vec![motor], notes.clone(), voices, &mut timer, &control).unwrap();
+
+            let result: Vec<u64> = edges.borrow().iter().filter(|e| e.1).map(|e| seek_mcs + e.0).collect();
+            result
+        };
+
+        // The voice steps the motor four times as fast as it would in full
+        // steps, from wherever it starts.
+        assert_eq!(edges(0), vec![125, 375, 625, 875, 1125, 1375, 1625, 1875]);
+        assert_eq!(edges(1100), vec![1125, 1375, 1625, 1875]);
+    }
+
+    #[test]
+    fn can_stop() {
+        let control: PlaybackControl = PlaybackControl::new();
+        let stopper: PlaybackControl = control.clone();
+        let notes: Vec<NoteInfo> = vec![note(1000, 10_000), note(0, 0).exit()];
+
+        assert_eq!(controlled_edges(notes, &control, Some((1500, Box::new(move || stopper.stop())))), vec![
+            (500, true), (1000, false), (1500, false),
+        ]);
+    }
+
+    #[test]
+    fn can_seek() {
+        let control: PlaybackControl = PlaybackControl::new();
+        let seeker: PlaybackControl = control.clone();
+        let notes: Vec<NoteInfo> = vec![
+            note(1000, 1000),
+            NoteInfo { next_note_index: 2, ..note(1000, 2000) },
+            note(0, 0).exit(),
+        ];
+
+        // Half a millisecond in, jump to 1.2 milliseconds in, which is 0.2
+        // milliseconds into the second note. Because the second note is
+        // rearticulated, the motor should be on until 1.5 milliseconds into
+        // the song.
+        assert_eq!(controlled_edges(notes, &control, Some((500, Box::new(move || seeker.seek(1200))))), vec![
+            (500, false), (500, true), (800, false), (1300, true), (1800, false),
+        ]);
+
+        assert_eq!(control.position_mcs(), 3000);
+    }
+
+    #[test]
+    fn can_seek_past_rests_on_missing_motors() {
+        let control: PlaybackControl = PlaybackControl::new();
+        let notes: Vec<NoteInfo> = vec![
+            note(1000, 1000),
+            note(0, 0).exit(),
+            NoteInfo { next_note_index: 3, motor_id: 1, ..note(0, 2000) },
+            NoteInfo { motor_id: 1, ..note(0, 0).exit() },
+        ];
+
+        control.seek(500);
+        play_note_info_array_with_control(vec![test_motor()], notes, vec![voice(0), voice(2)], &mut DummyTimer { }, &control).unwrap();
+        assert_eq!(control.position_mcs(), 2000);
+    }
+
+    #[test]
+    fn can_pause_and_resume() -> Result<(), Box<dyn Error>> {
+        let control: PlaybackControl = PlaybackControl::new();
+        let resumer: PlaybackControl = control.clone();
+        let notes: Vec<NoteInfo> = vec![note(1000, 10_000), note(0, 0).exit()];
+
+        control.pause();
+        assert!(control.is_paused());
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            resumer.resume();
+        });
+
+        play_note_info_array_with_control(vec![test_motor()], notes, vec![voice(0)], &mut DummyTimer { }, &control)?;
+        assert!(!control.is_paused());
+        Ok(())
     }
 }
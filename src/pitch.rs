@@ -0,0 +1,312 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+
+/// A pitch, given as a MIDI note number and an offset in cents. A tuning turns
+/// it into a frequency.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pitch {
+    /// The MIDI note number, so that 60 is middle C and 69 is A4.
+    pub note: i32,
+    /// How far the pitch is above the note, in hundredths of a semitone.
+    pub cents: f64,
+}
+
+impl Pitch {
+    pub fn midi(note: i32) -> Self {
+        Pitch { note, cents: 0.0 }
+    }
+
+    /// The pitch in the given octave with the given number of semitones above
+    /// C, so that `Pitch::from_octave(4, 9)` is A4.
+    pub fn from_octave(octave: i32, semitone: i32) -> Self {
+        Pitch::midi(octave * 12 + semitone + 12)
+    }
+
+    /// Parse a pitch name like "A4", "F#3", "Bb2" or "C-1", optionally
+    /// followed by an offset in cents like "+15" or "-7.5".
+    pub fn parse(name: &str) -> Option<Pitch> {
+        let mut chars = name.chars().peekable();
+
+        let mut semitone: i32 = match chars.next()?.to_ascii_uppercase() {
+            'C' => 0,
+            'D' => 2,
+            'E' => 4,
+            'F' => 5,
+            'G' => 7,
+            'A' => 9,
+            'B' => 11,
+            _ => return None,
+        };
+
+        while let Some(&c) = chars.peek() {
+            match c {
+                '#' => semitone += 1,
+                'b' => semitone -= 1,
+                _ => break,
+            }
+
+            chars.next();
+        }
+
+        let rest: String = chars.collect();
+
+        // The octave can be negative, so the cents start at the first sign
+        // after the octave's digits.
+        let octave_length: usize = match rest.strip_prefix('-') {
+            Some(digits) => 1 + digits.find(|c: char| !c.is_ascii_digit()).unwrap_or(digits.len()),
+            None => rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len()),
+        };
+
+        let octave: i32 = rest[..octave_length].parse().ok()?;
+
+        let cents: f64 = match &rest[octave_length..] {
+            "" => 0.0,
+            offset if offset.starts_with('+') || offset.starts_with('-') => offset.parse().ok()?,
+            _ => return None,
+        };
+
+        Some(Pitch::from_octave(octave, semitone).detune(cents))
+    }
+
+    /// The same pitch, moved up by the given number of cents.
+    pub fn detune(self, cents: f64) -> Self {
+        Pitch { cents: self.cents + cents, ..self }
+    }
+
+    pub fn frequency_hz(&self, tuning: &dyn Tuning) -> f64 {
+        tuning.frequency_hz(self.note) * 2.0_f64.powf(self.cents / 1200.0)
+    }
+
+    /// The frequency of the pitch in microhertz, as used by `NoteInfo`.
+    pub fn frequency_mchz(&self, tuning: &dyn Tuning) -> u64 {
+        (self.frequency_hz(tuning) * 1_000_000.0).round() as u64
+    }
+}
+
+/// A way of choosing the frequency of each note.
+pub trait Tuning {
+    /// The frequency of the given MIDI note number, in hertz.
+    fn frequency_hz(&self, note: i32) -> f64;
+}
+
+/// Twelve-tone equal temperament, with one note tuned to a given frequency.
+#[derive(Clone, Debug)]
+pub struct EqualTemperament {
+    pub reference_note: i32,
+    pub reference_hz: f64,
+}
+
+impl EqualTemperament {
+    pub fn new(reference_note: i32, reference_hz: f64) -> Self {
+        EqualTemperament { reference_note, reference_hz }
+    }
+}
+
+impl Default for EqualTemperament {
+    /// A4 is 440 Hz.
+    fn default() -> Self {
+        EqualTemperament::new(69, 440.0)
+    }
+}
+
+impl Tuning for EqualTemperament {
+    fn frequency_hz(&self, note: i32) -> f64 {
+        self.reference_hz * 2.0_f64.powf((note - self.reference_note) as f64 / 12.0)
+    }
+}
+
+/// A tuning made by repeating a scale, given as frequency ratios from its
+/// tonic, up and down from the tonic note. This is how Scala files work.
+#[derive(Clone, Debug)]
+pub struct ScaleTuning {
+    pub tonic_note: i32,
+    pub tonic_hz: f64,
+    /// The ratio of each degree of the scale after the tonic. The last one is
+    /// the ratio that the scale repeats at, which is usually 2, an octave.
+    pub ratios: Vec<f64>,
+}
+
+impl ScaleTuning {
+    /// A scale on the given tonic note. The tonic has the same frequency as in
+    /// standard equal temperament.
+    pub fn new(tonic_note: i32, ratios: Vec<f64>) -> Self {
+        let tonic_hz: f64 = EqualTemperament::default().frequency_hz(tonic_note);
+        ScaleTuning { tonic_note, tonic_hz, ratios }
+    }
+
+    /// Five-limit just intonation on the given tonic note.
+    pub fn just_intonation(tonic_note: i32) -> Self {
+        ScaleTuning::new(tonic_note, vec![
+            16.0 / 15.0, 9.0 / 8.0, 6.0 / 5.0, 5.0 / 4.0, 4.0 / 3.0, 45.0 / 32.0,
+            3.0 / 2.0, 8.0 / 5.0, 5.0 / 3.0, 9.0 / 5.0, 15.0 / 8.0, 2.0,
+        ])
+    }
+
+    /// Pythagorean tuning, built from pure fifths, on the given tonic note.
+    pub fn pythagorean(tonic_note: i32) -> Self {
+        ScaleTuning::new(tonic_note, vec![
+            256.0 / 243.0, 9.0 / 8.0, 32.0 / 27.0, 81.0 / 64.0, 4.0 / 3.0, 729.0 / 512.0,
+            3.0 / 2.0, 128.0 / 81.0, 27.0 / 16.0, 16.0 / 9.0, 243.0 / 128.0, 2.0,
+        ])
+    }
+}
+
+impl Tuning for ScaleTuning {
+    fn frequency_hz(&self, note: i32) -> f64 {
+        let length: i32 = self.ratios.len() as i32;
+        let steps: i32 = note - self.tonic_note;
+
+        let degree: usize = steps.rem_euclid(length) as usize;
+        let ratio: f64 = if degree == 0 { 1.0 } else { self.ratios[degree - 1] };
+        let period: f64 = self.ratios[self.ratios.len() - 1];
+
+        self.tonic_hz * ratio * period.powi(steps.div_euclid(length))
+    }
+}
+
+// A Scala file (.scl) describes a scale. Lines starting with `!` are comments.
+// The first other line is a description of the scale, and the next is the
+// number of degrees in it. Then there's a line for each degree after the
+// tonic, starting with either a ratio like `3/2` or `5`, or a number of cents
+// with a decimal point, like `701.955`. The last degree is the one that the
+// scale repeats at. For example:
+//
+//     ! pentatonic.scl
+//     A just pentatonic scale
+//      5
+//     !
+//      9/8
+//      5/4
+//      3/2
+//      5/3
+//      2/1
+
+#[derive(Debug)]
+pub struct ScalaReadError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScalaReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ScalaReadError { }
+
+fn parse_ratio(text: &str) -> Option<f64> {
+    let ratio: f64 = if text.contains('.') {
+        2.0_f64.powf(text.parse::<f64>().ok()? / 1200.0)
+    } else {
+        match text.split_once('/') {
+            Some((numerator, denominator)) =>
+                numerator.parse::<u64>().ok()? as f64 / denominator.parse::<u64>().ok()? as f64,
+            None => text.parse::<u64>().ok()? as f64,
+        }
+    };
+
+    if ratio > 0.0 && ratio.is_finite() { Some(ratio) } else { None }
+}
+
+/// Read a scale from the text of a Scala file, with its tonic on the given
+/// note.
+pub fn read_scala(text: &str, tonic_note: i32) -> Result<ScaleTuning, ScalaReadError> {
+    let mut lines = text.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.starts_with('!'))
+        .skip(1);
+
+    let (count_line, count_text): (usize, &str) = lines.next()
+        .ok_or_else(|| ScalaReadError { line: text.lines().count(), message: "the file has no scale".to_string() })?;
+
+    let count: usize = count_text.split_whitespace().next().and_then(|c| c.parse().ok())
+        .filter(|&count| count > 0)
+        .ok_or_else(|| ScalaReadError {
+            line: count_line,
+            message: format!("`{}` is not a number of degrees", count_text),
+        })?;
+
+    let mut ratios: Vec<f64> = vec![];
+
+    for (line, line_text) in lines.take(count) {
+        let value: &str = line_text.split_whitespace().next().unwrap_or("");
+
+        ratios.push(parse_ratio(value).ok_or_else(|| ScalaReadError {
+            line,
+            message: format!("`{}` is not a ratio or a number of cents", value),
+        })?);
+    }
+
+    if ratios.len() < count {
+        return Err(ScalaReadError {
+            line: count_line,
+            message: format!("the scale should have {} degrees, but it has {}", count, ratios.len()),
+        });
+    }
+
+    Ok(ScaleTuning::new(tonic_note, ratios))
+}
+
+/// Read a scale from a Scala file, with its tonic on the given note.
+pub fn read_scala_file(path: &str, tonic_note: i32) -> Result<ScaleTuning, Box<dyn Error>> {
+    let text: String = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    Ok(read_scala(&text, tonic_note).map_err(|e| format!("{}: {}", path, e))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pitch::*;
+
+    #[test]
+    fn can_parse_pitch_names() {
+        assert_eq!(Pitch::parse("A4"), Some(Pitch::midi(69)));
+        assert_eq!(Pitch::parse("c4"), Some(Pitch::midi(60)));
+        assert_eq!(Pitch::parse("F#3"), Some(Pitch::midi(54)));
+        assert_eq!(Pitch::parse("Bb2"), Some(Pitch::midi(46)));
+        assert_eq!(Pitch::parse("C-1"), Some(Pitch::midi(0)));
+        assert_eq!(Pitch::parse("A4+15"), Some(Pitch::midi(69).detune(15.0)));
+        assert_eq!(Pitch::parse("C-1-7.5"), Some(Pitch::midi(0).detune(-7.5)));
+
+        assert_eq!(Pitch::parse("H4"), None);
+        assert_eq!(Pitch::parse("A"), None);
+        assert_eq!(Pitch::parse("A4x"), None);
+    }
+
+    #[test]
+    fn can_tune_pitches() {
+        let equal: EqualTemperament = EqualTemperament::default();
+        assert_eq!(Pitch::midi(69).frequency_mchz(&equal), 440_000_000);
+        assert_eq!(Pitch::midi(81).frequency_mchz(&equal), 880_000_000);
+        assert_eq!(Pitch::midi(57).detune(1200.0).frequency_mchz(&equal), 440_000_000);
+        assert_eq!(Pitch::midi(69).frequency_mchz(&EqualTemperament::new(69, 432.0)), 432_000_000);
+
+        // In just intonation on A, E is a pure fifth above A.
+        let just: ScaleTuning = ScaleTuning::just_intonation(69);
+        assert_eq!(Pitch::midi(76).frequency_mchz(&just), 660_000_000);
+        assert_eq!(Pitch::midi(64).frequency_mchz(&just), 330_000_000);
+        assert_eq!(Pitch::midi(57).frequency_mchz(&just), 220_000_000);
+
+        // In Pythagorean tuning on C, the major third is 81/64.
+        let pythagorean: ScaleTuning = ScaleTuning::pythagorean(60);
+        let c4_hz: f64 = Pitch::midi(60).frequency_hz(&pythagorean);
+        assert!((Pitch::midi(64).frequency_hz(&pythagorean) - c4_hz * 81.0 / 64.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn can_read_scala_files() {
+        let text: &str = "! pentatonic.scl\nA just pentatonic scale\n 5\n!\n 9/8\n 5/4\n 701.955 cents\n 5/3\n 2\n";
+        let scale: ScaleTuning = read_scala(text, 69).unwrap();
+
+        assert_eq!(Pitch::midi(69).frequency_mchz(&scale), 440_000_000);
+        assert_eq!(Pitch::midi(71).frequency_mchz(&scale), 550_000_000);
+        assert_eq!(Pitch::midi(72).frequency_mchz(&scale), 660_000_000);
+        assert_eq!(Pitch::midi(74).frequency_mchz(&scale), 880_000_000);
+        assert_eq!(Pitch::midi(68).frequency_mchz(&scale), 366_666_667);
+
+        assert_eq!(read_scala("scale\n3\n9/8\n", 60).unwrap_err().line, 2);
+        assert_eq!(read_scala("scale\n2\n9/8\n0/1\n", 60).unwrap_err().line, 4);
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use crate::motor::Motor;
+use crate::notes::NoteInfo;
+use crate::notes::Voice;
+use crate::notes::play_note_info_array;
+use crate::notes::voice;
+use crate::timer::Timer;
+use crate::validate::check_song;
+
+/// Changes to make to a song when it's played, without editing the song
+/// itself, such as for rehearsing one voice at a time.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PlaybackOptions {
+    /// The voices to leave silent.
+    pub muted: Vec<usize>,
+    /// If any voices are soloed, only they are heard.
+    pub soloed: Vec<usize>,
+    /// The motor to play each voice on, instead of the motors that the song
+    /// says. Voices that are past the end of the list, or that are `None`, are
+    /// played on their own motors.
+    pub motors: Vec<Option<u8>>,
+    /// The microstep mode to play each voice in, for motors that can switch
+    /// modes. If there's just one, every voice is played in that mode.
+    pub microsteps: Vec<Option<u32>>,
+}
+
+impl PlaybackOptions {
+    /// Whether a voice is left silent, either because it's muted or because
+    /// other voices are soloed.
+    pub fn is_silent(&self, voice: usize) -> bool {
+        self.muted.contains(&voice) || (!self.soloed.is_empty() && !self.soloed.contains(&voice))
+    }
+
+    /// Make a copy of a song with the options applied. A silent voice still
+    /// plays all of its notes, as rests, so that it ends the song at the same
+    /// time as it would have.
+    pub fn apply(&self, notes: &[NoteInfo], voices: &[Voice]) -> Result<(Vec<NoteInfo>, Vec<Voice>), Box<dyn Error>> {
+        check_song(notes, voices, None)?;
+
+        let named_voices = self.muted.iter().chain(&self.soloed).copied()
+            .chain((0..self.motors.len()).filter(|&v| self.motors[v].is_some()))
+            .chain((0..self.microsteps.len()).filter(|&v| self.microsteps.len() > 1 && self.microsteps[v].is_some()));
+
+        for named_voice in named_voices {
+            if named_voice >= voices.len() {
+                return Err(format!("the song doesn't have a voice {} (it has {})", named_voice, voices.len()).into());
+            }
+        }
+
+        let microsteps = |voice_number: usize, old_voice: &Voice| match self.microsteps.as_slice() {
+            [microsteps] => microsteps.or(old_voice.microsteps()),
+            all_microsteps => all_microsteps.get(voice_number).copied().flatten().or(old_voice.microsteps()),
+        };
+
+        // Microstep modes belong to the voices, so they don't need the notes
+        // to be copied.
+        if self.muted.is_empty() && self.soloed.is_empty() && self.motors.is_empty() {
+            let new_voices: Vec<Voice> = voices.iter()
+                .enumerate()
+                .map(|(n, v)| voice(v.note_index()).with_microsteps(microsteps(n, v)))
+                .collect();
+
+            return Ok((notes.to_vec(), new_voices));
+        }
+
+        // Voices can share notes, so each voice gets its own copy of the
+        // notes it plays, which can then be changed without affecting the
+        // other voices.
+        let mut new_notes: Vec<NoteInfo> = vec![];
+        let mut new_voices: Vec<Voice> = vec![];
+
+        for (voice_number, old_voice) in voices.iter().enumerate() {
+            let silent: bool = self.is_silent(voice_number);
+            let motor: Option<u8> = self.motors.get(voice_number).copied().flatten();
+
+            let mut copies: HashMap<u32, u32> = HashMap::new();
+            let mut to_copy: Vec<u32> = vec![old_voice.note_index()];
+
+            while let Some(index) = to_copy.pop() {
+                if copies.contains_key(&index) {
+                    continue;
+                }
+
+                let mut note: NoteInfo = notes[index as usize];
+
+                if silent {
+                    note = note.rest();
+                }
+
+                if let Some(motor) = motor {
+                    note.motor_id = motor;
+                }
+
+                copies.insert(index, new_notes.len() as u32);
+                new_notes.push(note);
+
+                if !note.exit {
+                    if let Some(repeat) = note.repeat {
+                        to_copy.push(repeat.note_index);
+                    }
+
+                    to_copy.push(note.next_note_index);
+                }
+            }
+
+            for &new_index in copies.values() {
+                let note: &mut NoteInfo = &mut new_notes[new_index as usize];
+
+                if !note.exit {
+                    note.next_note_index = copies[&note.next_note_index];
+
+                    if let Some(repeat) = &mut note.repeat {
+                        repeat.note_index = copies[&repeat.note_index];
+                    }
+                }
+            }
+
+            new_voices.push(voice(copies[&old_voice.note_index()]).with_microsteps(microsteps(voice_number, old_voice)));
+        }
+
+        Ok((new_notes, new_voices))
+    }
+}
+
+/// Play a song with some voices muted, soloed or moved to other motors.
+pub fn play_with_options<M: Motor, T: Timer>(
+    pins: Vec<M>,
+    notes: Vec<NoteInfo>,
+    voices: Vec<Voice>,
+    timer: &mut T,
+    options: &PlaybackOptions,
+) -> Result<(), Box<dyn Error>> {
+    let (notes, voices) = options.apply(&notes, &voices)?;
+    play_note_info_array(pins, notes, voices, timer)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::playback::*;
+
+    fn note(next_note_index: u32, motor_id: u8) -> NoteInfo {
+        NoteInfo {
+            next_note_index,
+            motor_id,
+            exit: false,
+            stop: false,
+            frequency_mchz: 440_000_000,
+            length_mcs: 1000,
+            rearticulate: true,
+            repeat: None,
+        }
+    }
+
+    #[test]
+    fn can_mute_and_remap_voices() {
+        // Both voices share the exit note.
+        let notes: Vec<NoteInfo> = vec![note(3, 0), note(2, 1), note(3, 1), note(0, 0).rest().exit()];
+        let voices: Vec<Voice> = vec![voice(0), voice(1)];
+
+        let options: PlaybackOptions = PlaybackOptions { muted: vec![1], motors: vec![Some(1)], ..PlaybackOptions::default() };
+        let (new_notes, new_voices) = options.apply(&notes, &voices).unwrap();
+
+        assert_eq!(new_voices.iter().map(|v| v.note_index()).collect::<Vec<u32>>(), vec![0, 2]);
+
+        let played: Vec<(u32, u8, u64, bool)> = new_notes.iter()
+            .map(|n| (n.next_note_index, n.motor_id, n.frequency_mchz, n.exit))
+            .collect();
+        assert_eq!(played, vec![
+            (1, 1, 440_000_000, false), (0, 1, 0, true),
+            (3, 1, 0, false), (4, 1, 0, false), (0, 0, 0, true),
+        ]);
+    }
+
+    #[test]
+    fn can_set_microsteps() {
+        let notes: Vec<NoteInfo> = vec![note(0, 0).exit()];
+        let voices = || vec![voice(0), voice(0).with_microsteps(Some(2))];
+        let microsteps = |options: PlaybackOptions| -> Vec<Option<u32>> {
+            options.apply(&notes, &voices()).unwrap().1.iter().map(|v| v.microsteps()).collect()
+        };
+
+        assert_eq!(microsteps(PlaybackOptions::default()), vec![None, Some(2)]);
+        assert_eq!(microsteps(PlaybackOptions { microsteps: vec![Some(8)], ..PlaybackOptions::default() }), vec![Some(8), Some(8)]);
+        assert_eq!(microsteps(PlaybackOptions { microsteps: vec![Some(4), None], ..PlaybackOptions::default() }), vec![Some(4), Some(2)]);
+
+        let options: PlaybackOptions = PlaybackOptions { microsteps: vec![None, None, Some(4)], ..PlaybackOptions::default() };
+        assert!(options.apply(&notes, &voices()).is_err());
+    }
+
+    #[test]
+    fn can_solo_voices() {
+        let options: PlaybackOptions = PlaybackOptions { soloed: vec![1], ..PlaybackOptions::default() };
+        assert!(options.is_silent(0));
+        assert!(!options.is_silent(1));
+
+        let notes: Vec<NoteInfo> = vec![note(0, 0).exit()];
+        assert!(options.apply(&notes, &[voice(0)]).is_err());
+    }
+}
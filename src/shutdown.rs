@@ -0,0 +1,114 @@
+use std::panic;
+use std::process;
+use std::sync::{
+    Mutex,
+    MutexGuard,
+    TryLockError,
+    atomic::AtomicBool,
+    atomic::AtomicU64,
+    atomic::Ordering,
+};
+
+use crate::control::PlaybackControl;
+
+// If ambrose stops in the middle of a song, any output pins that were left
+// high can keep the drivers holding current. Normally the motors are reset when
+// the song stops and again when they're dropped, but the functions here make
+// sure that they're also reset when ambrose is interrupted or panics.
+//
+// Anything that needs resetting registers a reset action, which stays
+// registered until the returned guard is dropped.
+
+type ResetAction = Box<dyn Fn() + Send>;
+
+static RESET_ACTIONS: Mutex<Vec<(u64, ResetAction)>> = Mutex::new(vec![]);
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Keeps a reset action registered for as long as it's alive.
+pub struct ResetGuard {
+    id: u64,
+}
+
+impl Drop for ResetGuard {
+    fn drop(&mut self) {
+        lock_actions().retain(|(id, _)| *id != self.id);
+    }
+}
+
+fn lock_actions() -> MutexGuard<'static, Vec<(u64, ResetAction)>> {
+    // A panic while the list was locked doesn't leave it in a bad state.
+    RESET_ACTIONS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Register an action to run if ambrose is interrupted or panics.
+pub fn on_emergency<F: Fn() + Send + 'static>(action: F) -> ResetGuard {
+    let id: u64 = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    lock_actions().push((id, Box::new(action)));
+    ResetGuard { id }
+}
+
+/// Run all of the registered reset actions.
+pub fn emergency_reset() {
+    // If the list is locked by a thread that isn't going to let go of it,
+    // it's better to skip the reset than to hang.
+    let actions: MutexGuard<Vec<(u64, ResetAction)>> = match RESET_ACTIONS.try_lock() {
+        Ok(actions) => actions,
+        Err(TryLockError::Poisoned(e)) => e.into_inner(),
+        Err(TryLockError::WouldBlock) => return,
+    };
+
+    for (_, action) in actions.iter() {
+        action();
+    }
+}
+
+/// Make SIGINT and SIGTERM stop the song (and, the second time, exit right
+/// away), and make panics run the reset actions.
+pub fn install_handlers(control: PlaybackControl) -> Result<(), ctrlc::Error> {
+    let default_hook = panic::take_hook();
+
+    panic::set_hook(Box::new(move |info| {
+        emergency_reset();
+        default_hook(info);
+    }));
+
+    ctrlc::set_handler(move || {
+        let nothing_to_reset: bool = lock_actions().is_empty();
+
+        if INTERRUPTED.swap(true, Ordering::SeqCst) || nothing_to_reset {
+            emergency_reset();
+            process::exit(130);
+        }
+
+        eprintln!("Stopping...");
+        control.stop();
+    })
+}
+
+/// Whether ambrose has been interrupted by a signal.
+pub fn was_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::shutdown::*;
+
+    #[test]
+    fn runs_registered_actions() {
+        let count: Arc<AtomicU64> = Arc::new(AtomicU64::new(0));
+        let action_count: Arc<AtomicU64> = count.clone();
+
+        let guard: ResetGuard = on_emergency(move || { action_count.fetch_add(1, Ordering::SeqCst); });
+        emergency_reset();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        drop(guard);
+        emergency_reset();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,10 +1,21 @@
 use crate::notes::NoteInfo;
+use crate::notes::Repeat;
+use crate::notes::Voice;
+use crate::notes::voice;
 
+use crate::tempo::TempoMap;
+
+#[derive(Debug)]
 pub struct SongBuilder {
     pub notes: Vec<NoteInfo>,
     pub voices: Vec<VoiceInfo>,
+    /// The tempo used to work out the lengths of notes added with `add_beats`.
+    pub tempo: TempoMap,
+    /// The length in beats of each note added with `add_beats`.
+    beats: Vec<Option<f64>>,
 }
 
+#[derive(Debug)]
 pub struct VoiceInfo {
     pub first_note_index: u32,
     last_note_index: u32,
@@ -12,7 +23,12 @@ pub struct VoiceInfo {
 
 impl SongBuilder {
     pub fn new() -> Self {
-        SongBuilder { notes: Vec::<NoteInfo>::new(), voices: Vec::<VoiceInfo>::new() }
+        SongBuilder {
+            notes: Vec::<NoteInfo>::new(),
+            voices: Vec::<VoiceInfo>::new(),
+            tempo: TempoMap::default(),
+            beats: Vec::<Option<f64>>::new(),
+        }
     }
 
     pub fn add(&mut self, voice: u8, note: NoteInfo) {
@@ -36,7 +52,184 @@ impl SongBuilder {
         }
 
         self.notes.push(NoteInfo { motor_id: voice, ..note });
+        self.beats.push(None);
 
         self.voices[voice as usize].last_note_index = new_note_index;
     }
+
+    /// Add a note that lasts the given number of beats. Its length in
+    /// microseconds is worked out from `tempo` when the song is built.
+    pub fn add_beats(&mut self, voice: u8, note: NoteInfo, beats: f64) {
+        self.add(voice, note);
+        *self.beats.last_mut().unwrap() = Some(beats);
+    }
+
+    /// Repeat a section of a voice, so that it's played the given number of
+    /// times in all. The section starts at `first_note_index`, which is
+    /// usually the length of `notes` from just before the section was added,
+    /// and ends at the last note added to the voice so far. Sections can be
+    /// nested, as long as each one ends on a different note.
+    ///
+    /// Panics if the last note already ends a repeat, or if `first_note_index`
+    /// is after it.
+    pub fn repeat(&mut self, voice: u8, first_note_index: u32, times: u32) {
+        let last_note_index: u32 = self.voices[voice as usize].last_note_index;
+        let last_note: &mut NoteInfo = &mut self.notes[last_note_index as usize];
+
+        assert!(last_note.repeat.is_none(), "note {} already ends a repeat", last_note_index);
+        assert!(first_note_index <= last_note_index, "a repeat can't start after it ends");
+
+        last_note.repeat = Some(Repeat { note_index: first_note_index, times });
+    }
+
+    /// Work out the lengths of the notes that were given in beats, and return
+    /// the finished song. Voices that don't end with an exit note are given
+    /// one, so that each voice ends after its last note.
+    pub fn build(mut self) -> (Vec<NoteInfo>, Vec<Voice>) {
+        for voice in 0..self.voices.len() {
+            let last_note: NoteInfo = self.notes[self.voices[voice].last_note_index as usize];
+
+            if !last_note.exit {
+                self.add(voice as u8, NoteInfo { length_mcs: 0, repeat: None, ..last_note.rest().exit() });
+            }
+        }
+
+        for voice_info in &self.voices {
+            let mut index: u32 = voice_info.first_note_index;
+            let mut beat: f64 = 0.0;
+            let mut start_mcs: u64 = 0;
+            let mut note_start_mcs: Vec<u64> = vec![0; self.notes.len()];
+
+            // Each note's length is worked out the first time that it's
+            // played, so the repeats themselves don't have to be played
+            // through. A note that's repeated keeps the length that it had the
+            // first time, so the tempo should only change outside of repeats.
+            loop {
+                note_start_mcs[index as usize] = start_mcs;
+                let note: &mut NoteInfo = &mut self.notes[index as usize];
+
+                // Working from the start time of each note, rather than adding
+                // up rounded lengths, keeps the voices in time with each other.
+                match self.beats[index as usize] {
+                    Some(beats) => {
+                        beat += beats;
+                        let end_mcs: u64 = self.tempo.beat_mcs(beat).round() as u64;
+                        note.length_mcs = end_mcs.saturating_sub(start_mcs);
+                        start_mcs = end_mcs;
+                    }
+                    None => {
+                        start_mcs += note.length_mcs;
+                        beat = self.tempo.mcs_beat(start_mcs as f64);
+                    }
+                }
+
+                // The rest of the passes through a section take as long as the
+                // first one, including any repeats inside it.
+                if let Some(repeat) = note.repeat {
+                    if repeat.times > 1 {
+                        let pass_mcs: u64 = start_mcs.saturating_sub(note_start_mcs[repeat.note_index as usize]);
+                        start_mcs = start_mcs.saturating_add(pass_mcs.saturating_mul(repeat.times as u64 - 1));
+                        beat = self.tempo.mcs_beat(start_mcs as f64);
+                    }
+                }
+
+                if index == voice_info.last_note_index {
+                    break;
+                }
+
+                index = note.next_note_index;
+            }
+        }
+
+        let voices: Vec<Voice> = self.voices.iter().map(|v| voice(v.first_note_index)).collect();
+
+        (self.notes, voices)
+    }
+}
+
+impl Default for SongBuilder {
+    fn default() -> Self {
+        SongBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::songbuilder::*;
+
+    fn note(length_mcs: u64) -> NoteInfo {
+        NoteInfo {
+            next_note_index: 0,
+            motor_id: 0,
+            exit: false,
+            stop: false,
+            frequency_mchz: 440_000_000,
+            length_mcs,
+            rearticulate: true,
+            repeat: None,
+        }
+    }
+
+    #[test]
+    fn works_out_lengths_in_beats() {
+        let mut b: SongBuilder = SongBuilder::new();
+        b.tempo = TempoMap::new(60.0);
+        b.tempo.set_tempo(2.0, 120.0);
+
+        b.add_beats(0, note(0), 1.5);   b.add(1, note(250_000));
+        b.add_beats(0, note(0), 1.0);   b.add_beats(1, note(0), 1.5);
+                                        b.add_beats(1, note(0), 1.0);
+
+        let (notes, voices) = b.build();
+        let lengths: Vec<u64> = notes.iter().map(|n| n.length_mcs).collect();
+
+        // The tempo doubles at beat 2, so the notes that cross it are partly
+        // at each tempo. Both voices are given exit notes at the end.
+        assert_eq!(lengths, vec![1_500_000, 250_000, 750_000, 1_500_000, 625_000, 0, 0]);
+        assert!(notes[5].exit && notes[6].exit);
+        assert_eq!(voices.iter().map(|v| v.note_index()).collect::<Vec<u32>>(), vec![0, 1]);
+    }
+
+    #[test]
+    fn keeps_time_through_repeats() {
+        let mut b: SongBuilder = SongBuilder::new();
+        b.tempo = TempoMap::new(60.0);
+        b.tempo.set_tempo(4.0, 120.0);
+
+        // Voice 0 plays its first two beats twice, so its last note is after
+        // the tempo change.
+        b.add_beats(0, note(0), 1.0);
+        b.add_beats(0, note(0), 1.0);
+        b.repeat(0, 0, 2);
+        b.add_beats(0, note(0), 1.0);
+
+        let (notes, _) = b.build();
+        let lengths: Vec<u64> = notes.iter().map(|n| n.length_mcs).collect();
+
+        assert_eq!(lengths, vec![1_000_000, 1_000_000, 500_000, 0]);
+        assert_eq!(notes[1].repeat, Some(Repeat { note_index: 0, times: 2 }));
+    }
+
+    #[test]
+    fn works_out_lengths_without_playing_repeats() {
+        let mut b: SongBuilder = SongBuilder::new();
+        b.tempo = TempoMap::new(60.0);
+        b.tempo.set_tempo(10.0, 120.0);
+
+        // The second note is played three times on each of the two passes
+        // through the first three, so the last note starts at beat 10.
+        b.add_beats(0, note(0), 1.0);
+        b.add_beats(0, note(0), 1.0);
+        b.repeat(0, 1, 3);
+        b.add_beats(0, note(0), 1.0);
+        b.repeat(0, 0, 2);
+        b.add_beats(0, note(0), 1.0);
+        b.add_beats(1, note(0), 1.0);
+        b.repeat(1, 4, u32::MAX);
+
+        let (notes, _) = b.build();
+        let lengths: Vec<u64> = notes.iter().map(|n| n.length_mcs).collect();
+
+        assert_eq!(lengths, vec![1_000_000, 1_000_000, 1_000_000, 500_000, 1_000_000, 0, 0]);
+    }
 }
@@ -1 +1,312 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
 
+use crate::notes::NoteInfo;
+
+use crate::pitch::{
+    EqualTemperament,
+    Pitch,
+    read_scala_file,
+    ScaleTuning,
+    Tuning,
+};
+
+use crate::songbuilder::SongBuilder;
+
+// A song file is a plain-text file laid out much like the songs in the songs
+// module: one line per moment in time, with one column per voice, and the
+// columns separated by `|`. For example:
+//
+//     // Comments start with two slashes.
+//     beat 62500
+//
+//     B1 20      | F#3 4
+//                | B3 4
+//     rest 2     | D#4 4 kick
+//     B1 24
+//
+// The `beat` line gives the length of one duration unit in microseconds, and
+// it has to come before any notes. Each note is a pitch name (with `#` for
+// sharp and `b` for flat, an octave number, so that A4 is 440 Hz, and
+// optionally an offset in cents, like `A4+15`) or the word `rest`, then a
+// duration in beats, then any of the words `slur`, `kick`, `exit` and `stop`.
+// Empty columns are skipped, and a voice can't be used before all of the
+// voices to its left have been used. Each voice ends after its last note, or
+// at an exit note, and a stop note ends the whole song.
+//
+// Notes are in equal temperament with A4 at 440 Hz, unless a `tuning` line
+// before them says otherwise:
+//
+//     tuning equal 432              // equal temperament, with A4 at 432 Hz
+//     tuning just D4                // just intonation in D
+//     tuning pythagorean D4         // Pythagorean tuning in D
+//     tuning scala my_scale.scl D4  // a scale from a Scala file, starting on D
+
+#[derive(Debug)]
+pub struct SongReadError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SongReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for SongReadError { }
+
+fn error(line: usize, column: usize, message: String) -> SongReadError {
+    SongReadError { line, column, message }
+}
+
+/// Split some text into words, each paired with its (1-based) column number.
+fn words(text: &str, first_column: usize) -> Vec<(usize, &str)> {
+    let mut result: Vec<(usize, &str)> = vec![];
+    let mut start: Option<(usize, usize)> = None;
+
+    for (column, (offset, c)) in (first_column..).zip(text.char_indices()) {
+        if c.is_whitespace() {
+            if let Some((start_offset, start_column)) = start.take() {
+                result.push((start_column, &text[start_offset..offset]));
+            }
+        } else if start.is_none() {
+            start = Some((offset, column));
+        }
+    }
+
+    if let Some((start_offset, start_column)) = start {
+        result.push((start_column, &text[start_offset..]));
+    }
+
+    result
+}
+
+/// Parse a `tuning` line, given its words after `tuning`.
+fn parse_tuning(line: usize, column: usize, args: &[(usize, &str)]) -> Result<Box<dyn Tuning>, SongReadError> {
+    let tonic = |index: usize| -> Result<i32, SongReadError> {
+        match args.get(index) {
+            Some(&(tonic_column, name)) if args.len() == index + 1 => Pitch::parse(name)
+                .map(|pitch| pitch.note)
+                .ok_or_else(|| error(line, tonic_column, format!("`{}` is not a pitch name", name))),
+            _ => Err(error(line, column, "expected a tonic pitch name at the end of the line".to_string())),
+        }
+    };
+
+    match args.first() {
+        Some((_, "equal")) => match args.get(1) {
+            None => Ok(Box::new(EqualTemperament::default())),
+            Some(&(hz_column, hz)) if args.len() == 2 => match hz.parse::<f64>() {
+                Ok(hz) if hz > 0.0 => Ok(Box::new(EqualTemperament::new(69, hz))),
+                _ => Err(error(line, hz_column, format!("`{}` is not a frequency", hz))),
+            },
+            _ => Err(error(line, column, "expected `tuning equal [<frequency of A4>]`".to_string())),
+        },
+        Some((_, "just")) => Ok(Box::new(ScaleTuning::just_intonation(tonic(1)?))),
+        Some((_, "pythagorean")) => Ok(Box::new(ScaleTuning::pythagorean(tonic(1)?))),
+        Some(&(path_column, "scala")) => {
+            let tonic_note: i32 = tonic(2)?;
+            let path: &str = args.get(1).map(|&(_, path)| path).unwrap_or_default();
+
+            read_scala_file(path, tonic_note)
+                .map(|scale| Box::new(scale) as Box<dyn Tuning>)
+                .map_err(|e| error(line, path_column, e.to_string()))
+        }
+        _ => Err(error(line, column,
+            "expected `tuning equal`, `tuning just`, `tuning pythagorean` or `tuning scala`".to_string())),
+    }
+}
+
+fn parse_note(
+    line: usize,
+    cell: &[(usize, &str)],
+    beat_duration: u64,
+    tuning: &dyn Tuning,
+) -> Result<NoteInfo, SongReadError> {
+    let (pitch_column, pitch_name) = cell[0];
+
+    let frequency_mchz: u64 = if pitch_name == "rest" {
+        0
+    } else {
+        Pitch::parse(pitch_name).map(|pitch| pitch.frequency_mchz(tuning)).ok_or_else(||
+            error(line, pitch_column, format!("`{}` is not a pitch name or `rest`", pitch_name)))?
+    };
+
+    let length_mcs: u64 = match cell.get(1) {
+        Some(&(column, word)) => {
+            let duration: u64 = word.parse().map_err(|_|
+                error(line, column, format!("`{}` is not a duration", word)))?;
+
+            duration.checked_mul(beat_duration).ok_or_else(||
+                error(line, column, format!("a duration of {} is too long", word)))?
+        }
+        None => return Err(error(line, pitch_column, "note has no duration".to_string())),
+    };
+
+    let mut note: NoteInfo = NoteInfo {
+        next_note_index: 0,
+        motor_id: 0,
+        exit: false,
+        stop: false,
+        frequency_mchz,
+        length_mcs,
+        rearticulate: true,
+        repeat: None,
+    };
+
+    for &(column, word) in &cell[2..] {
+        note = match word {
+            "slur" => note.slur(),
+            "kick" => note.kick(),
+            "exit" => note.exit(),
+            "stop" => note.stop(),
+            _ => return Err(error(line, column, format!("unknown note option `{}`", word))),
+        };
+    }
+
+    Ok(note)
+}
+
+/// Read a song from the text of a song file.
+pub fn read_song(text: &str) -> Result<SongBuilder, SongReadError> {
+    let mut builder: SongBuilder = SongBuilder::new();
+    let mut beat_duration: Option<u64> = None;
+    let mut tuning: Box<dyn Tuning> = Box::new(EqualTemperament::default());
+
+    for (line_index, full_line) in text.lines().enumerate() {
+        let line: usize = line_index + 1;
+        let content: &str = match full_line.find("//") {
+            Some(comment_start) => &full_line[..comment_start],
+            None => full_line,
+        };
+
+        let line_words: Vec<(usize, &str)> = words(content, 1);
+
+        match line_words.first() {
+            None => continue,
+            Some(&(column, "beat")) => {
+                beat_duration = match line_words.get(1) {
+                    Some(&(value_column, value)) if line_words.len() == 2 =>
+                        Some(value.parse().map_err(|_|
+                            error(line, value_column, format!("`{}` is not a beat duration", value)))?),
+                    _ => return Err(error(line, column, "expected `beat <microseconds>`".to_string())),
+                };
+                continue;
+            }
+            Some(&(column, "tuning")) => {
+                tuning = parse_tuning(line, column, &line_words[1..])?;
+                continue;
+            }
+            Some(_) => { }
+        }
+
+        let mut cell_column: usize = 1;
+
+        for (voice, cell_text) in content.split('|').enumerate() {
+            let cell: Vec<(usize, &str)> = words(cell_text, cell_column);
+            cell_column += cell_text.chars().count() + 1;
+
+            let first_column: usize = match cell.first() {
+                Some(&(column, _)) => column,
+                None => continue,
+            };
+
+            let beat_duration: u64 = beat_duration.ok_or_else(||
+                error(line, first_column, "notes must come after a `beat` line".to_string()))?;
+
+            if voice > u8::MAX as usize {
+                return Err(error(line, first_column, format!("too many voices (at most {})", u8::MAX as usize + 1)));
+            }
+
+            if voice > builder.voices.len() {
+                return Err(error(line, first_column,
+                    format!("voice {} is used before voice {}", voice, builder.voices.len())));
+            }
+
+            let note: NoteInfo = parse_note(line, &cell, beat_duration, tuning.as_ref())?;
+            builder.add(voice as u8, note);
+        }
+    }
+
+    Ok(builder)
+}
+
+/// Read a song from a song file.
+pub fn read_song_file(path: &str) -> Result<SongBuilder, Box<dyn Error>> {
+    let text: String = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    Ok(read_song(&text).map_err(|e| format!("{}: {}", path, e))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::songreader::*;
+
+    #[test]
+    fn can_read_song() {
+        let text: &str = "
+            // a test
+            beat 1000
+
+            A4 2       | C#5 1 kick
+                       | rest 1
+            Bb3 1 slur | A4 1 exit
+        ";
+
+        let builder: SongBuilder = read_song(text).unwrap();
+
+        assert_eq!(builder.voices.len(), 2);
+        assert_eq!(builder.voices[0].first_note_index, 0);
+        assert_eq!(builder.voices[1].first_note_index, 1);
+
+        let notes: &Vec<NoteInfo> = &builder.notes;
+        assert_eq!(notes.len(), 5);
+
+        assert_eq!(notes[0].frequency_mchz, 440_000_000);
+        assert_eq!(notes[0].length_mcs, 2000);
+        assert_eq!(notes[0].next_note_index, 3);
+
+        assert_eq!(notes[1].frequency_mchz, 554_365_262);
+        assert_eq!(notes[1].motor_id, 1);
+        assert_eq!(notes[1].next_note_index, 2);
+
+        assert_eq!(notes[2].frequency_mchz, 0);
+
+        assert_eq!(notes[3].frequency_mchz, 233_081_881);
+        assert!(!notes[3].rearticulate);
+
+        assert!(notes[4].exit);
+
+        assert!(read_song("beat 1000\nA4 1 stop").unwrap().notes[0].stop);
+    }
+
+    #[test]
+    fn reports_error_positions() {
+        let error: SongReadError = read_song("beat 1000\nA4 1 | H4 1").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 8));
+
+        let error: SongReadError = read_song("A4 1").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 1));
+
+        let error: SongReadError = read_song("beat 1000\n  | A4 1").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 5));
+
+        let error: SongReadError = read_song("beat 1000\nA4 1 loud").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 6));
+
+        let error: SongReadError = read_song("beat 1000000\nA4 1 | A4 99999999999999").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 11));
+
+        let error: SongReadError = read_song("tuning just H4").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 13));
+    }
+
+    #[test]
+    fn can_change_tuning() {
+        let builder: SongBuilder = read_song("beat 1000\nA4+1200 1\ntuning just A4\nE5 1\ntuning equal 432\nA4 1").unwrap();
+        let frequencies: Vec<u64> = builder.notes.iter().map(|n| n.frequency_mchz).collect();
+
+        assert_eq!(frequencies, vec![880_000_000, 660_000_000, 432_000_000]);
+    }
+}
@@ -1,2 +1,62 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use crate::midireader::read_midi_file;
+
+use crate::notes::NoteInfo;
+use crate::notes::Voice;
+
+use crate::pitch::{
+    EqualTemperament,
+    Pitch,
+};
+
+use crate::songbuilder::SongBuilder;
+
+use crate::songreader::read_song_file;
+
 pub mod hallelujah;
+pub mod pachelbel;
 pub mod peaceofmind;
+
+/// The names of the built-in songs.
+pub const SONGS: [&str; 3] = ["hallelujah", "peaceofmind", "pachelbel"];
+
+/// A note for the built-in songs, which are in equal temperament, given as an
+/// octave and a number of semitones above C. The note's length is given when
+/// it's added to the song.
+fn note(octave: i32, semitone: i32) -> NoteInfo {
+    NoteInfo {
+        next_note_index: 0,
+        motor_id: 0,
+        exit: false,
+        stop: false,
+        frequency_mchz: Pitch::from_octave(octave, semitone).frequency_mchz(&EqualTemperament::default()),
+        length_mcs: 0,
+        rearticulate: true,
+        repeat: None,
+    }
+}
+
+/// Whether a song is a MIDI file, going by its name.
+pub fn is_midi_file(song: &str) -> bool {
+    song.ends_with(".mid") || song.ends_with(".midi")
+}
+
+/// Load a song, given either the name of a built-in song or the path of a song
+/// file or MIDI file. If `channel_voices` is given, it maps a MIDI file's
+/// channels to voices, as in `read_midi`.
+pub fn load_song(
+    song: &str,
+    channel_voices: Option<&HashMap<u8, u8>>,
+) -> Result<(Vec<NoteInfo>, Vec<Voice>), Box<dyn Error>> {
+    let builder: SongBuilder = match song {
+        "hallelujah" => hallelujah::build_song(),
+        "peaceofmind" => peaceofmind::build_song(),
+        "pachelbel" => return Ok(pachelbel::build_song()),
+        path if is_midi_file(path) => read_midi_file(path, channel_voices)?,
+        path => read_song_file(path)?,
+    };
+
+    Ok(builder.build())
+}
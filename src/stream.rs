@@ -0,0 +1,156 @@
+use std::sync::mpsc::{
+    Receiver,
+    sync_channel,
+    SyncSender,
+    TryRecvError,
+};
+
+#[cfg(feature = "rodio")]
+use std::time::Duration;
+
+#[cfg(feature = "rodio")]
+use rodio::Source;
+
+// The audio backend doesn't render the whole song before playing it. Instead,
+// the engine plays the song on a `SimpleAudioTimer` as usual, and the timer
+// sends the samples through a channel in chunks as it goes. The other end of the
+// channel is a `SampleStream`, which the audio device reads from.
+//
+// The channel only holds a few chunks, so once it's full the engine has to wait
+// for the audio device to catch up, which keeps the engine running in real time
+// just ahead of what's being heard.
+
+/// The number of samples sent through the channel at a time.
+pub const CHUNK_SAMPLES: usize = 1024;
+
+/// The number of chunks that can be waiting to be played. At 44.1 kHz, four
+/// chunks is a little under a tenth of a second, which is how far behind the
+/// engine a pause or a stop is heard.
+const BUFFERED_CHUNKS: usize = 4;
+
+pub type SampleSender = SyncSender<Vec<f32>>;
+
+/// Samples coming from a song that's being played. If the song has been
+/// paused, or the engine has fallen behind, the stream is silent until more
+/// samples arrive, and it ends once the song has ended and all of the samples
+/// have been played.
+pub struct SampleStream {
+    receiver: Receiver<Vec<f32>>,
+    sample_rate: u32,
+    chunk: Vec<f32>,
+    position: usize,
+}
+
+pub fn sample_stream(sample_rate: u32) -> (SampleSender, SampleStream) {
+    let (sender, receiver) = sync_channel(BUFFERED_CHUNKS);
+
+    (sender, SampleStream { receiver, sample_rate, chunk: vec![], position: 0 })
+}
+
+impl SampleStream {
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+impl Iterator for SampleStream {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        while self.position >= self.chunk.len() {
+            // The audio device can't be kept waiting, so this mustn't block.
+            match self.receiver.try_recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                Err(TryRecvError::Empty) => return Some(0.0),
+                Err(TryRecvError::Disconnected) => return None,
+            }
+        }
+
+        self.position += 1;
+        Some(self.chunk[self.position - 1])
+    }
+}
+
+#[cfg(feature = "rodio")]
+impl Source for SampleStream {
+    // The stream's format never changes, so it's all one frame.
+    fn current_frame_len(&self) -> Option<usize> { None }
+
+    fn channels(&self) -> u16 { 1 }
+
+    fn sample_rate(&self) -> u32 { self.sample_rate }
+
+    fn total_duration(&self) -> Option<Duration> { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::backend::render_audio;
+    use crate::machine::Machine;
+    use crate::motor::SimpleAudioMotor;
+    use crate::notes::*;
+    use crate::stream::*;
+    use crate::timer::SimpleAudioTimer;
+
+    #[test]
+    fn plays_samples_as_they_arrive() {
+        let (sender, mut stream) = sample_stream(44100);
+
+        // Nothing has been sent yet, so the stream is silent.
+        assert_eq!(stream.next(), Some(0.0));
+
+        sender.send(vec![0.5, -0.5]).unwrap();
+        sender.send(vec![0.25]).unwrap();
+        assert_eq!(stream.next(), Some(0.5));
+        assert_eq!(stream.next(), Some(-0.5));
+        assert_eq!(stream.next(), Some(0.25));
+        assert_eq!(stream.next(), Some(0.0));
+
+        sender.send(vec![0.1]).unwrap();
+        drop(sender);
+        assert_eq!(stream.next(), Some(0.1));
+        assert_eq!(stream.next(), None);
+    }
+
+    #[test]
+    fn streams_the_same_samples_as_rendering() {
+        let note: NoteInfo = NoteInfo {
+            next_note_index: 1,
+            motor_id: 0,
+            exit: false,
+            stop: false,
+            frequency_mchz: 440_000_000,
+            length_mcs: 100_000,
+            rearticulate: true,
+            repeat: None,
+        };
+        let notes: Vec<NoteInfo> = vec![note, note.rest().exit()];
+
+        let (sender, stream) = sample_stream(44100);
+
+        // The stream only holds a few chunks, so the engine has to wait for
+        // the stream to be read, as it would for the audio device.
+        let player: thread::JoinHandle<()> = thread::spawn({
+            let notes: Vec<NoteInfo> = notes.clone();
+            move || {
+                let pins: Vec<SimpleAudioMotor> = vec![SimpleAudioMotor::new()];
+                let mut timer: SimpleAudioTimer = SimpleAudioTimer::streaming(44100, &pins, sender);
+                play_note_info_array(pins, notes, vec![voice(0)], &mut timer).unwrap();
+                timer.flush().unwrap();
+            }
+        });
+
+        // The stream is silent while it waits for samples, so silence is left
+        // out of both.
+        let streamed: Vec<f32> = stream.filter(|&sample| sample != 0.0).collect();
+        player.join().unwrap();
+
+        let rendered: Vec<f32> = render_audio(&Machine::default(), notes, vec![voice(0)]).unwrap();
+        assert_eq!(streamed, rendered.into_iter().filter(|&sample| sample != 0.0).collect::<Vec<f32>>());
+    }
+}
@@ -0,0 +1,210 @@
+// A tempo map says how long each beat of a song lasts, so that note lengths
+// can be given in beats and worked out in microseconds once the whole song has
+// been built.
+//
+// The map is a list of segments, each starting at a beat and lasting until the
+// next one starts. Within a segment, the tempo changes linearly (per beat) from
+// the segment's starting tempo to its ending tempo, so a segment with the same
+// starting and ending tempo has a steady tempo. The last segment always has a
+// steady tempo, and lasts forever.
+
+const MCS_PER_MINUTE: f64 = 60_000_000.0;
+
+#[derive(Clone, Debug)]
+pub struct TempoMap {
+    segments: Vec<TempoSegment>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TempoSegment {
+    start_beat: f64,
+    start_bpm: f64,
+    end_bpm: f64,
+}
+
+impl TempoSegment {
+    /// How long the first `beats` beats of the segment take, if the segment is
+    /// `length` beats long.
+    fn mcs(&self, beats: f64, length: f64) -> f64 {
+        if self.start_bpm == self.end_bpm {
+            return beats * MCS_PER_MINUTE / self.start_bpm;
+        }
+
+        // The tempo is start_bpm + k * beats, and each beat takes one
+        // over the tempo, so the time taken is the integral of that.
+        let k: f64 = (self.end_bpm - self.start_bpm) / length;
+        MCS_PER_MINUTE / k * ((self.start_bpm + k * beats) / self.start_bpm).ln()
+    }
+
+    /// How many beats into the segment it is after `mcs` microseconds.
+    fn beats(&self, mcs: f64, length: f64) -> f64 {
+        if self.start_bpm == self.end_bpm {
+            return mcs * self.start_bpm / MCS_PER_MINUTE;
+        }
+
+        let k: f64 = (self.end_bpm - self.start_bpm) / length;
+        self.start_bpm * ((k * mcs / MCS_PER_MINUTE).exp() - 1.0) / k
+    }
+}
+
+impl TempoMap {
+    /// A tempo map with a steady tempo, in beats per minute.
+    pub fn new(bpm: f64) -> Self {
+        assert!(bpm > 0.0, "the tempo must be positive");
+
+        TempoMap { segments: vec![TempoSegment { start_beat: 0.0, start_bpm: bpm, end_bpm: bpm }] }
+    }
+
+    fn push(&mut self, segment: TempoSegment) {
+        let last: TempoSegment = *self.segments.last().unwrap();
+
+        assert!(segment.start_beat >= last.start_beat, "tempo changes must be made in order");
+        assert!(segment.end_bpm > 0.0, "the tempo must be positive");
+
+        if segment.start_beat == last.start_beat {
+            self.segments.pop();
+        }
+
+        self.segments.push(segment);
+    }
+
+    /// Change to a new tempo at the given beat. Changes have to be made in
+    /// order, and can't be made in the middle of a ramp.
+    pub fn set_tempo(&mut self, beat: f64, bpm: f64) {
+        self.push(TempoSegment { start_beat: beat, start_bpm: bpm, end_bpm: bpm });
+    }
+
+    /// Change the tempo gradually from `start_beat` to `end_beat`, ending up at
+    /// the given tempo. This makes an accelerando or a ritardando.
+    pub fn ramp(&mut self, start_beat: f64, end_beat: f64, bpm: f64) {
+        assert!(end_beat > start_beat, "a ramp has to end after it starts");
+
+        let start_bpm: f64 = self.bpm_at(start_beat);
+
+        self.push(TempoSegment { start_beat, start_bpm, end_bpm: bpm });
+        self.set_tempo(end_beat, bpm);
+    }
+
+    /// Make the beats from `start_beat` to `end_beat` last `factor` times as
+    /// long, for a fermata, and then go back to the tempo from before.
+    pub fn fermata(&mut self, start_beat: f64, end_beat: f64, factor: f64) {
+        let bpm: f64 = self.bpm_at(start_beat);
+
+        self.set_tempo(start_beat, bpm / factor);
+        self.set_tempo(end_beat, bpm);
+    }
+
+    /// The segments, each with the beat that it ends at.
+    fn segments(&self) -> impl Iterator<Item = (&TempoSegment, f64)> {
+        let ends = self.segments.iter().skip(1).map(|s| s.start_beat).chain(Some(f64::INFINITY));
+        self.segments.iter().zip(ends)
+    }
+
+    /// The tempo at the given beat, in beats per minute.
+    pub fn bpm_at(&self, beat: f64) -> f64 {
+        let (segment, end_beat) = self.segments().find(|&(_, end_beat)| beat < end_beat).unwrap();
+
+        if segment.start_bpm == segment.end_bpm {
+            segment.start_bpm
+        } else {
+            let progress: f64 = (beat - segment.start_beat) / (end_beat - segment.start_beat);
+            segment.start_bpm + (segment.end_bpm - segment.start_bpm) * progress
+        }
+    }
+
+    /// The time of the given beat, in microseconds from the start of the song.
+    pub fn beat_mcs(&self, beat: f64) -> f64 {
+        let mut start_mcs: f64 = 0.0;
+
+        for (segment, end_beat) in self.segments() {
+            let length: f64 = end_beat - segment.start_beat;
+
+            if beat < end_beat {
+                return start_mcs + segment.mcs(beat - segment.start_beat, length);
+            }
+
+            start_mcs += segment.mcs(length, length);
+        }
+
+        unreachable!()
+    }
+
+    /// The beat at the given time, in microseconds from the start of the song.
+    /// This is the opposite of `beat_mcs`.
+    pub fn mcs_beat(&self, mcs: f64) -> f64 {
+        let mut start_mcs: f64 = 0.0;
+
+        for (segment, end_beat) in self.segments() {
+            let length: f64 = end_beat - segment.start_beat;
+            let end_mcs: f64 = start_mcs + segment.mcs(length, length);
+
+            if mcs < end_mcs {
+                return segment.start_beat + segment.beats(mcs - start_mcs, length);
+            }
+
+            start_mcs = end_mcs;
+        }
+
+        unreachable!()
+    }
+}
+
+impl Default for TempoMap {
+    /// 120 beats per minute, which is also the default for MIDI files.
+    fn default() -> Self {
+        TempoMap::new(120.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tempo::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-6, "{} is not close to {}", a, b);
+    }
+
+    #[test]
+    fn can_change_tempo() {
+        let mut tempo: TempoMap = TempoMap::new(120.0);
+        tempo.set_tempo(4.0, 60.0);
+
+        assert_close(tempo.beat_mcs(2.0), 1_000_000.0);
+        assert_close(tempo.beat_mcs(6.0), 4_000_000.0);
+        assert_close(tempo.mcs_beat(4_000_000.0), 6.0);
+        assert_close(tempo.bpm_at(3.0), 120.0);
+        assert_close(tempo.bpm_at(4.0), 60.0);
+    }
+
+    #[test]
+    fn can_ramp_tempo() {
+        let mut tempo: TempoMap = TempoMap::new(60.0);
+        tempo.ramp(1.0, 2.0, 120.0);
+
+        // The beat from 1 to 2 takes ln(2) seconds.
+        assert_close(tempo.beat_mcs(2.0), 1_000_000.0 + 1_000_000.0 * 2.0_f64.ln());
+        assert_close(tempo.beat_mcs(3.0), 1_500_000.0 + 1_000_000.0 * 2.0_f64.ln());
+        assert_close(tempo.bpm_at(1.5), 90.0);
+
+        for &beat in &[0.5, 1.25, 1.5, 2.0, 7.0] {
+            assert_close(tempo.mcs_beat(tempo.beat_mcs(beat)), beat);
+        }
+    }
+
+    #[test]
+    fn can_hold_fermatas() {
+        let mut tempo: TempoMap = TempoMap::new(60.0);
+        tempo.fermata(2.0, 3.0, 3.0);
+
+        assert_close(tempo.beat_mcs(3.0), 5_000_000.0);
+        assert_close(tempo.beat_mcs(4.0), 6_000_000.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn changes_must_be_in_order() {
+        let mut tempo: TempoMap = TempoMap::new(60.0);
+        tempo.set_tempo(4.0, 90.0);
+        tempo.set_tempo(2.0, 120.0);
+    }
+}
@@ -6,7 +6,6 @@ use std::{
 };
 
 use std::error::Error;
-#[cfg(not(feature = "raspi"))]
 use std::iter::repeat;
 
 #[cfg(feature = "raspi")]
@@ -17,9 +16,13 @@ use nix::{
     time::ClockId,
 };
 
-#[cfg(not(feature = "raspi"))]
 use crate::motor::SimpleAudioMotor;
 
+use crate::stream::{
+    CHUNK_SAMPLES,
+    SampleSender,
+};
+
 pub trait Timer {
     /// Tell the timer to wait the given number of microseconds.
     /// The timer is expected to keep track of a "target time," and this
@@ -37,44 +40,75 @@ pub trait Timer {
     fn reset(&mut self) -> Result<(), Box<dyn Error>>;
 }
 
+#[cfg(test)]
 pub struct DummyTimer { }
 
+#[cfg(test)]
 impl Timer for DummyTimer {
     fn wait_microseconds(&mut self, _duration: u64) -> Result<(), Box<dyn Error>> { Ok(()) }
     fn reset(&mut self) -> Result<(), Box<dyn Error>> { Ok(()) }
 }
 
-#[cfg(not(feature = "raspi"))]
 pub struct SimpleAudioTimer {
     sample_rate: u32,
     motors: Vec<SimpleAudioMotor>,
     time_mcs: u64,
+    /// The amplitude so far of the sample that's being played, which is the
+    /// average over the whole sample, so that short pulses still come through.
+    partial_sample: f32,
     pub data: Vec<f32>,
+    sender: Option<SampleSender>,
 }
 
-#[cfg(not(feature = "raspi"))]
 impl SimpleAudioTimer {
-    pub fn new(sample_rate: u32, motors: &Vec<SimpleAudioMotor>) -> Self {
+    pub fn new(sample_rate: u32, motors: &[SimpleAudioMotor]) -> Self {
         SimpleAudioTimer {
             sample_rate,
-            motors: motors.clone(),
+            motors: motors.to_vec(),
             time_mcs: 0,
-            data: vec![]
+            partial_sample: 0.0,
+            data: vec![],
+            sender: None,
+        }
+    }
+
+    /// Make a timer that sends its samples to `sender` in chunks as it goes,
+    /// instead of keeping them in `data`. Sending waits while the stream's
+    /// buffer is full, so the song plays in real time.
+    pub fn streaming(sample_rate: u32, motors: &[SimpleAudioMotor], sender: SampleSender) -> Self {
+        SimpleAudioTimer { sender: Some(sender), ..SimpleAudioTimer::new(sample_rate, motors) }
+    }
+
+    /// Finish the sample that's being played, leaving the rest of it silent,
+    /// and send any samples that are waiting for a full chunk. This is for
+    /// the end of the song.
+    pub fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        // Times are counted in millionths of a sample, as in
+        // `wait_microseconds`.
+        let time: u64 = (self.sample_rate as u64) * self.time_mcs;
+
+        if time > time / 1_000_000 * 1_000_000 {
+            self.data.push(self.partial_sample);
+            self.partial_sample = 0.0;
         }
+
+        self.send_chunks(1)
+    }
+
+    fn send_chunks(&mut self, min_samples: usize) -> Result<(), Box<dyn Error>> {
+        if let Some(sender) = &self.sender {
+            while self.data.len() >= min_samples {
+                let chunk: Vec<f32> = self.data.drain(..self.data.len().min(CHUNK_SAMPLES)).collect();
+                sender.send(chunk).map_err(|_| "the audio stream was closed")?;
+            }
+        }
+
+        Ok(())
     }
 }
 
-#[cfg(not(feature = "raspi"))]
 impl Timer for SimpleAudioTimer {
     fn wait_microseconds(&mut self, duration: u64) -> Result<(), Box<dyn Error>> {
-        let old_sample_count: u64 =
-            (self.sample_rate as u64) * self.time_mcs / 1_000_000;
-        self.time_mcs += duration;
-        let new_sample_count: u64 =
-            (self.sample_rate as u64) * self.time_mcs / 1_000_000;
-
-        let samples_to_add: u64 = new_sample_count - old_sample_count;
-
         let mut new_amplitude: f32 = 0.0;
 
         for motor in self.motors.iter() {
@@ -85,9 +119,28 @@ impl Timer for SimpleAudioTimer {
             }
         }
 
-        self.data.extend(repeat(new_amplitude).take(samples_to_add as usize));
+        // Times are counted in millionths of a sample, so that each sample is
+        // 1,000,000 of them long.
+        let start: u64 = (self.sample_rate as u64) * self.time_mcs;
+        self.time_mcs += duration;
+        let end: u64 = (self.sample_rate as u64) * self.time_mcs;
+
+        let first_sample: u64 = start / 1_000_000;
+        let last_sample: u64 = end / 1_000_000;
+        let fraction = |from: u64, to: u64| (to - from) as f32 / 1_000_000.0;
+
+        if first_sample == last_sample {
+            self.partial_sample += new_amplitude * fraction(start, end);
+        } else {
+            self.partial_sample += new_amplitude * fraction(start, (first_sample + 1) * 1_000_000);
+            self.data.push(self.partial_sample);
+            // `repeat_n` would need Rust 1.82.
+            #[allow(unknown_lints, clippy::manual_repeat_n)]
+            self.data.extend(repeat(new_amplitude).take((last_sample - first_sample - 1) as usize));
+            self.partial_sample = new_amplitude * fraction(last_sample * 1_000_000, end);
+        }
 
-        Ok(())
+        self.send_chunks(CHUNK_SAMPLES)
     }
 
     fn reset(&mut self) -> Result<(), Box<dyn Error>> { Ok(()) }
@@ -105,6 +158,13 @@ impl NixTimer {
     }
 }
 
+#[cfg(feature = "raspi")]
+impl Default for NixTimer {
+    fn default() -> Self {
+        NixTimer::new()
+    }
+}
+
 #[cfg(feature = "raspi")]
 fn now() -> Result<TimeSpec, nix::Error> {
     // return clock_gettime(ClockId::CLOCK_PROCESS_CPUTIME_ID);
@@ -125,3 +185,35 @@ impl Timer for NixTimer {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::motor::Motor;
+    use crate::timer::*;
+
+    #[test]
+    fn averages_each_sample() {
+        let mut motor: SimpleAudioMotor = SimpleAudioMotor::new();
+        let mut timer: SimpleAudioTimer = SimpleAudioTimer::new(100_000, &[motor.clone()]);
+
+        // A pulse much shorter than a sample still moves it.
+        timer.wait_microseconds(15).unwrap();
+        motor.advance();
+        timer.wait_microseconds(2).unwrap();
+        motor.reset();
+        timer.wait_microseconds(23).unwrap();
+        motor.advance();
+        timer.wait_microseconds(20).unwrap();
+
+        // Flushing finishes the last sample, which is only half played.
+        timer.wait_microseconds(5).unwrap();
+        timer.flush().unwrap();
+
+        let expected: Vec<f32> = vec![-0.1, -0.06, -0.1, -0.1, 0.1, 0.1, 0.05];
+        assert_eq!(timer.data.len(), expected.len());
+
+        for (sample, expected) in timer.data.iter().zip(expected) {
+            assert!((sample - expected).abs() < 1e-6, "{} isn't {}", sample, expected);
+        }
+    }
+}
@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::slice;
+
+use crate::notes::NoteInfo;
+use crate::notes::Repeat;
+use crate::notes::Voice;
+
+// Checks a note graph before it's played, so that a bad index shows up as an
+// error up front instead of as a panic in the middle of a performance.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SongProblem {
+    /// The song doesn't have any voices, so it would never end.
+    NoVoices,
+    /// A voice starts at a note that doesn't exist.
+    VoiceStartOutOfRange { voice: usize, note_index: u32 },
+    /// A note is followed by a note that doesn't exist.
+    NextNoteOutOfRange { note_index: u32, next_note_index: u32 },
+    /// A stop note isn't an exit note, so the voice would play on past it.
+    StopWithoutExit { note_index: u32 },
+    /// A note repeats back to a note that doesn't exist.
+    RepeatOutOfRange { note_index: u32, repeat_note_index: u32 },
+    /// A note repeats its section no times at all.
+    ZeroRepeatTimes { note_index: u32 },
+    /// A note repeats back to a note that doesn't lead back to it.
+    UnclosedRepeat { note_index: u32, repeat_note_index: u32 },
+    /// Two repeated sections overlap without one being inside the other.
+    OverlappingRepeats { note_index: u32, other_note_index: u32 },
+    /// A voice plays a note on a motor that doesn't exist. Rests can be on
+    /// any motor, because they don't use it.
+    MissingMotor { voice: usize, note_index: u32, motor_id: u8 },
+    /// Some notes aren't played by any voice.
+    UnreachableNotes { note_indices: Vec<u32> },
+    /// A voice never reaches an exit note, so the song only ends if another
+    /// voice reaches a stop note.
+    VoiceNeverExits { voice: usize },
+    /// A voice gets stuck in a loop of notes that take no time at all.
+    EmptyLoop { voice: usize },
+}
+
+impl SongProblem {
+    /// Whether the song can't be played at all because of this problem.
+    pub fn is_fatal(&self) -> bool {
+        !matches!(self, SongProblem::UnreachableNotes { .. } | SongProblem::VoiceNeverExits { .. })
+    }
+}
+
+impl fmt::Display for SongProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SongProblem::NoVoices =>
+                write!(f, "the song has no voices"),
+            SongProblem::VoiceStartOutOfRange { voice, note_index } =>
+                write!(f, "voice {} starts at note {}, which doesn't exist", voice, note_index),
+            SongProblem::NextNoteOutOfRange { note_index, next_note_index } =>
+                write!(f, "note {} is followed by note {}, which doesn't exist", note_index, next_note_index),
+            SongProblem::StopWithoutExit { note_index } =>
+                write!(f, "note {} stops the song without ending its voice", note_index),
+            SongProblem::RepeatOutOfRange { note_index, repeat_note_index } =>
+                write!(f, "note {} repeats back to note {}, which doesn't exist", note_index, repeat_note_index),
+            SongProblem::ZeroRepeatTimes { note_index } =>
+                write!(f, "note {} repeats its section 0 times", note_index),
+            SongProblem::UnclosedRepeat { note_index, repeat_note_index } =>
+                write!(f, "note {} repeats back to note {}, which doesn't lead back to it", note_index, repeat_note_index),
+            SongProblem::OverlappingRepeats { note_index, other_note_index } =>
+                write!(f, "the sections repeated by notes {} and {} overlap", note_index, other_note_index),
+            SongProblem::MissingMotor { voice, note_index, motor_id } =>
+                write!(f, "voice {} plays note {} on motor {}, which doesn't exist", voice, note_index, motor_id),
+            SongProblem::UnreachableNotes { note_indices } =>
+                write!(f, "{} notes aren't played by any voice (the first is note {})",
+                    note_indices.len(), note_indices[0]),
+            SongProblem::VoiceNeverExits { voice } =>
+                write!(f, "voice {} never reaches an exit note", voice),
+            SongProblem::EmptyLoop { voice } =>
+                write!(f, "voice {} gets stuck in a loop of notes with no length", voice),
+        }
+    }
+}
+
+/// The error returned when a song has fatal problems.
+#[derive(Debug)]
+pub struct InvalidSong {
+    pub problems: Vec<SongProblem>,
+}
+
+impl fmt::Display for InvalidSong {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let problems: Vec<String> = self.problems.iter().map(|p| p.to_string()).collect();
+        write!(f, "invalid song: {}", problems.join("; "))
+    }
+}
+
+impl Error for InvalidSong { }
+
+/// Find all of the problems with a song. If `motor_count` is given, also check
+/// that every note is played on a motor that exists.
+pub fn validate_song(notes: &[NoteInfo], voices: &[Voice], motor_count: Option<usize>) -> Vec<SongProblem> {
+    let mut problems: Vec<SongProblem> = vec![];
+
+    if voices.is_empty() {
+        problems.push(SongProblem::NoVoices);
+    }
+
+    // The notes in each repeated section, by the note that repeats it.
+    let mut sections: HashMap<u32, Vec<u32>> = HashMap::new();
+
+    for (index, note) in notes.iter().enumerate() {
+        if !note.exit && note.next_note_index as usize >= notes.len() {
+            problems.push(SongProblem::NextNoteOutOfRange {
+                note_index: index as u32,
+                next_note_index: note.next_note_index,
+            });
+        }
+
+        if note.stop && !note.exit {
+            problems.push(SongProblem::StopWithoutExit { note_index: index as u32 });
+        }
+
+        if let Some(repeat) = note.repeat {
+            if note.exit {
+                continue;
+            }
+
+            if repeat.note_index as usize >= notes.len() {
+                problems.push(SongProblem::RepeatOutOfRange {
+                    note_index: index as u32,
+                    repeat_note_index: repeat.note_index,
+                });
+            } else if repeat.times == 0 {
+                problems.push(SongProblem::ZeroRepeatTimes { note_index: index as u32 });
+            } else if repeat.times > 1 {
+                match repeated_section(notes, index as u32, repeat) {
+                    Ok(section) => {
+                        sections.insert(index as u32, section);
+                    }
+                    Err(problem) => problems.push(problem),
+                }
+            }
+        }
+    }
+
+    let mut reachable: Vec<bool> = vec![false; notes.len()];
+
+    for (voice_number, voice) in voices.iter().enumerate() {
+        let mut index: u32 = voice.note_index();
+
+        if index as usize >= notes.len() {
+            problems.push(SongProblem::VoiceStartOutOfRange { voice: voice_number, note_index: index });
+            continue;
+        }
+
+        // Each repeat only goes back a limited number of times, and the
+        // sections have been checked above, so the voice has only looped
+        // when it comes back to a note by following each note to the next
+        // one. Each note is kept with how long the voice had been playing
+        // when it got there, not counting the repeats.
+        let mut visited: HashMap<u32, u64> = HashMap::new();
+        let mut elapsed_mcs: u64 = 0;
+        let mut missing_motors: HashSet<u8> = HashSet::new();
+
+        // Follow the voice until it exits, loops or runs off the end. Running
+        // off the end, or into a bad repeat, has already been reported above.
+        while (index as usize) < notes.len() {
+            if let Some(&loop_start_mcs) = visited.get(&index) {
+                problems.push(SongProblem::VoiceNeverExits { voice: voice_number });
+
+                if elapsed_mcs == loop_start_mcs {
+                    problems.push(SongProblem::EmptyLoop { voice: voice_number });
+                }
+
+                break;
+            }
+
+            visited.insert(index, elapsed_mcs);
+            reachable[index as usize] = true;
+
+            let note: NoteInfo = notes[index as usize];
+
+            if note.exit {
+                break;
+            }
+
+            let section: &[u32] = match (note.repeat, sections.get(&index)) {
+                (_, Some(section)) => section,
+                (Some(repeat), None) if repeat.times > 1 => break,
+                _ => slice::from_ref(&index),
+            };
+
+            // A repeat plays its whole section again, even the notes that the
+            // voice doesn't get to otherwise.
+            for &played_index in section {
+                reachable[played_index as usize] = true;
+
+                let played_note: NoteInfo = notes[played_index as usize];
+
+                if let Some(motor_count) = motor_count {
+                    let played: bool = played_note.frequency_mchz > 0;
+
+                    if played && played_note.motor_id as usize >= motor_count
+                        && missing_motors.insert(played_note.motor_id)
+                    {
+                        problems.push(SongProblem::MissingMotor {
+                            voice: voice_number,
+                            note_index: played_index,
+                            motor_id: played_note.motor_id,
+                        });
+                    }
+                }
+            }
+
+            elapsed_mcs += note.length_mcs;
+            index = note.next_note_index;
+        }
+    }
+
+    let unreachable: Vec<u32> = (0..notes.len() as u32).filter(|&i| !reachable[i as usize]).collect();
+
+    if !unreachable.is_empty() {
+        problems.push(SongProblem::UnreachableNotes { note_indices: unreachable });
+    }
+
+    problems
+}
+
+/// The notes in the section that the given note repeats, following each note
+/// to the next one from the start of the section to the note itself. Any
+/// section that's repeated inside it has to start inside it too.
+fn repeated_section(notes: &[NoteInfo], note_index: u32, repeat: Repeat) -> Result<Vec<u32>, SongProblem> {
+    let unclosed: SongProblem = SongProblem::UnclosedRepeat { note_index, repeat_note_index: repeat.note_index };
+    let mut section: Vec<u32> = vec![];
+    let mut in_section: HashSet<u32> = HashSet::new();
+    let mut index: u32 = repeat.note_index;
+
+    loop {
+        if index as usize >= notes.len() || !in_section.insert(index) {
+            return Err(unclosed);
+        }
+
+        section.push(index);
+
+        if index == note_index {
+            return Ok(section);
+        }
+
+        let note: NoteInfo = notes[index as usize];
+
+        if note.exit {
+            return Err(unclosed);
+        }
+
+        if let Some(inner) = note.repeat {
+            let in_range: bool = (inner.note_index as usize) < notes.len();
+
+            if in_range && inner.times > 1 && !in_section.contains(&inner.note_index) {
+                return Err(SongProblem::OverlappingRepeats { note_index, other_note_index: index });
+            }
+        }
+
+        index = note.next_note_index;
+    }
+}
+
+/// Check that a song can be played on the given number of motors, returning
+/// the fatal problems with it as an error.
+pub fn check_song(notes: &[NoteInfo], voices: &[Voice], motor_count: Option<usize>) -> Result<(), InvalidSong> {
+    let problems: Vec<SongProblem> = validate_song(notes, voices, motor_count)
+        .into_iter()
+        .filter(|p| p.is_fatal())
+        .collect();
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(InvalidSong { problems })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::notes::voice;
+    use crate::validate::*;
+
+    fn note(next_note_index: u32, motor_id: u8) -> NoteInfo {
+        NoteInfo {
+            next_note_index,
+            motor_id,
+            exit: false,
+            stop: false,
+            frequency_mchz: 440_000_000,
+            length_mcs: 1000,
+            rearticulate: true,
+            repeat: None,
+        }
+    }
+
+    #[test]
+    fn accepts_good_songs() {
+        let notes: Vec<NoteInfo> = vec![note(1, 0), note(0, 0).exit(), note(1, 1)];
+        assert_eq!(validate_song(&notes, &[voice(0), voice(2)], Some(2)), vec![]);
+    }
+
+    #[test]
+    fn finds_problems() {
+        let notes: Vec<NoteInfo> = vec![note(1, 0), note(0, 2), note(7, 0), note(0, 0).exit()];
+
+        assert_eq!(validate_song(&notes, &[voice(0), voice(5)], Some(2)), vec![
+            SongProblem::NextNoteOutOfRange { note_index: 2, next_note_index: 7 },
+            SongProblem::MissingMotor { voice: 0, note_index: 1, motor_id: 2 },
+            SongProblem::VoiceNeverExits { voice: 0 },
+            SongProblem::VoiceStartOutOfRange { voice: 1, note_index: 5 },
+            SongProblem::UnreachableNotes { note_indices: vec![2, 3] },
+        ]);
+
+        assert_eq!(validate_song(&notes, &[], None), vec![
+            SongProblem::NoVoices,
+            SongProblem::NextNoteOutOfRange { note_index: 2, next_note_index: 7 },
+            SongProblem::UnreachableNotes { note_indices: vec![0, 1, 2, 3] },
+        ]);
+
+        let notes: Vec<NoteInfo> = vec![NoteInfo { stop: true, ..note(0, 0) }];
+        assert_eq!(validate_song(&notes, &[voice(0)], None), vec![
+            SongProblem::StopWithoutExit { note_index: 0 },
+            SongProblem::VoiceNeverExits { voice: 0 },
+        ]);
+    }
+
+    #[test]
+    fn only_fatal_problems_are_errors() {
+        let notes: Vec<NoteInfo> = vec![note(0, 0), note(0, 0)];
+        assert!(check_song(&notes, &[voice(0)], Some(1)).is_ok());
+        assert!(check_song(&notes, &[voice(0)], Some(0)).is_err());
+    }
+
+    #[test]
+    fn follows_repeats() {
+        let repeat: Repeat = Repeat { note_index: 0, times: 3 };
+        let notes: Vec<NoteInfo> = vec![note(1, 0), NoteInfo { repeat: Some(repeat), ..note(2, 0) }, note(0, 0).exit()];
+        assert_eq!(validate_song(&notes, &[voice(0)], Some(1)), vec![]);
+
+        let repeat: Repeat = Repeat { note_index: 4, times: 3 };
+        let notes: Vec<NoteInfo> = vec![NoteInfo { repeat: Some(repeat), ..note(1, 0) }, note(0, 0).exit()];
+        assert_eq!(validate_song(&notes, &[voice(0)], Some(1)), vec![
+            SongProblem::RepeatOutOfRange { note_index: 0, repeat_note_index: 4 },
+            SongProblem::UnreachableNotes { note_indices: vec![1] },
+        ]);
+    }
+
+    #[test]
+    fn checks_repeats_without_playing_them() {
+        let repeat = |note_index: u32, times: u32, next_note_index: u32| {
+            NoteInfo { repeat: Some(Repeat { note_index, times }), ..note(next_note_index, 0) }
+        };
+
+        let notes: Vec<NoteInfo> = vec![note(1, 0), repeat(1, 1000, 2), repeat(0, u32::MAX, 3), note(0, 0).exit()];
+        assert_eq!(validate_song(&notes, &[voice(0)], Some(1)), vec![]);
+
+        let notes: Vec<NoteInfo> = vec![repeat(0, 0, 1), note(0, 0).exit()];
+        assert_eq!(validate_song(&notes, &[voice(0)], Some(1)), vec![
+            SongProblem::ZeroRepeatTimes { note_index: 0 },
+        ]);
+
+        let notes: Vec<NoteInfo> = vec![note(1, 0), repeat(2, 2, 2), note(0, 0).exit()];
+        assert_eq!(validate_song(&notes, &[voice(0)], Some(1)), vec![
+            SongProblem::UnclosedRepeat { note_index: 1, repeat_note_index: 2 },
+            SongProblem::UnreachableNotes { note_indices: vec![2] },
+        ]);
+
+        let notes: Vec<NoteInfo> = vec![note(1, 0), repeat(0, 2, 2), repeat(1, 2, 3), note(0, 0).exit()];
+        assert_eq!(validate_song(&notes, &[voice(0)], Some(1)), vec![
+            SongProblem::OverlappingRepeats { note_index: 2, other_note_index: 1 },
+            SongProblem::UnreachableNotes { note_indices: vec![3] },
+        ]);
+    }
+
+    #[test]
+    fn finds_empty_loops() {
+        let empty_note: NoteInfo = NoteInfo { length_mcs: 0, ..note(2, 0) };
+        let notes: Vec<NoteInfo> = vec![note(1, 0), empty_note, NoteInfo { next_note_index: 1, ..empty_note }];
+
+        assert_eq!(validate_song(&notes, &[voice(0)], Some(1)), vec![
+            SongProblem::VoiceNeverExits { voice: 0 },
+            SongProblem::EmptyLoop { voice: 0 },
+        ]);
+    }
+}
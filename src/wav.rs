@@ -0,0 +1,119 @@
+use std::error::Error;
+use std::fs;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SampleFormat {
+    /// 16-bit signed integer samples, which nearly everything can play.
+    Pcm16,
+    /// 32-bit floating-point samples, exactly as rendered.
+    Float32,
+}
+
+fn push_u16(bytes: &mut Vec<u8>, value: u16) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Encode mono audio samples as the contents of a WAV file.
+pub fn wav_bytes(data: &[f32], sample_rate: u32, format: SampleFormat) -> Vec<u8> {
+    let (format_tag, bytes_per_sample): (u16, u32) = match format {
+        SampleFormat::Pcm16 => (WAVE_FORMAT_PCM, 2),
+        SampleFormat::Float32 => (WAVE_FORMAT_IEEE_FLOAT, 4),
+    };
+
+    let data_length: u32 = data.len() as u32 * bytes_per_sample;
+
+    // Formats other than PCM need a `cbSize` field in the `fmt ` chunk and a
+    // `fact` chunk giving the number of samples.
+    let is_pcm: bool = format == SampleFormat::Pcm16;
+    let fmt_length: u32 = if is_pcm { 16 } else { 18 };
+    let fact_length: u32 = if is_pcm { 0 } else { 12 };
+
+    let mut bytes: Vec<u8> = vec![];
+
+    bytes.extend_from_slice(b"RIFF");
+    push_u32(&mut bytes, 4 + (8 + fmt_length) + fact_length + (8 + data_length));
+    bytes.extend_from_slice(b"WAVE");
+
+    bytes.extend_from_slice(b"fmt ");
+    push_u32(&mut bytes, fmt_length);
+    push_u16(&mut bytes, format_tag);
+    push_u16(&mut bytes, 1);
+    push_u32(&mut bytes, sample_rate);
+    push_u32(&mut bytes, sample_rate * bytes_per_sample);
+    push_u16(&mut bytes, bytes_per_sample as u16);
+    push_u16(&mut bytes, bytes_per_sample as u16 * 8);
+
+    if !is_pcm {
+        push_u16(&mut bytes, 0);
+
+        bytes.extend_from_slice(b"fact");
+        push_u32(&mut bytes, 4);
+        push_u32(&mut bytes, data.len() as u32);
+    }
+
+    bytes.extend_from_slice(b"data");
+    push_u32(&mut bytes, data_length);
+
+    for &sample in data {
+        match format {
+            SampleFormat::Pcm16 => {
+                let value: i16 = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            SampleFormat::Float32 => bytes.extend_from_slice(&sample.to_le_bytes()),
+        }
+    }
+
+    bytes
+}
+
+/// Write mono audio samples to a WAV file.
+pub fn write_wav_file(
+    path: &str,
+    data: &[f32],
+    sample_rate: u32,
+    format: SampleFormat,
+) -> Result<(), Box<dyn Error>> {
+    fs::write(path, wav_bytes(data, sample_rate, format)).map_err(|e| format!("{}: {}", path, e))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wav::*;
+
+    #[test]
+    fn can_write_pcm() {
+        let bytes: Vec<u8> = wav_bytes(&[0.0, 0.5, -2.0], 44100, SampleFormat::Pcm16);
+
+        assert_eq!(bytes.len(), 44 + 6);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(&bytes[4..8], &(44 + 6 - 8u32).to_le_bytes());
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(&bytes[20..22], &WAVE_FORMAT_PCM.to_le_bytes());
+        assert_eq!(&bytes[24..28], &44100u32.to_le_bytes());
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(&bytes[40..44], &6u32.to_le_bytes());
+        assert_eq!(&bytes[44..], &[0, 0, 0x00, 0x40, 0x01, 0x80]);
+    }
+
+    #[test]
+    fn can_write_float() {
+        let bytes: Vec<u8> = wav_bytes(&[0.25], 8000, SampleFormat::Float32);
+
+        assert_eq!(bytes.len(), 58 + 4);
+        assert_eq!(&bytes[4..8], &(58 + 4 - 8u32).to_le_bytes());
+        assert_eq!(&bytes[20..22], &WAVE_FORMAT_IEEE_FLOAT.to_le_bytes());
+        assert_eq!(&bytes[38..46], b"fact\x04\0\0\0");
+        assert_eq!(&bytes[46..50], &1u32.to_le_bytes());
+        assert_eq!(&bytes[50..54], b"data");
+        assert_eq!(&bytes[58..], &0.25f32.to_le_bytes());
+    }
+}